@@ -0,0 +1,241 @@
+// podiff-review
+// review po changes easily
+//
+// The MIT License (MIT)
+//
+// Copyright 2015 est31 <MTest31@outlook.com>
+/*
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+*/
+
+use regex::Regex;
+use po::PoEntry;
+
+/// The placeholder syntaxes we can check
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FormatKind {
+	/// printf style, used by c-format, lua-format, php-format and others
+	C,
+	/// printf style with %(name)s
+	Python,
+	/// {0} and {name}
+	Brace,
+	/// %1, %2, used by qt-format and kde-format
+	Qt,
+}
+
+impl FormatKind {
+	fn from_flag(flag: &str) -> Option<FormatKind> {
+		return match flag {
+			"c-format" | "objc-format" | "lua-format" | "php-format" | "perl-format" | "gcc-internal-format" => Some(FormatKind::C),
+			"python-format" => Some(FormatKind::Python),
+			"python-brace-format" | "csharp-format" => Some(FormatKind::Brace),
+			"qt-format" | "kde-format" => Some(FormatKind::Qt),
+			_ => None,
+		};
+	}
+
+	fn name(&self) -> &'static str {
+		return match *self {
+			FormatKind::C => "printf",
+			FormatKind::Python => "python",
+			FormatKind::Brace => "brace",
+			FormatKind::Qt => "qt",
+		};
+	}
+}
+
+/// Returns the placeholder syntaxes used by the entry, from its flags,
+/// or guessed from its msgid if it has no format flags.
+pub fn format_kinds(entry: &PoEntry) -> Vec<FormatKind> {
+	let from_flags = entry.flags.iter()
+		.filter_map(|f| FormatKind::from_flag(f))
+		.collect::<Vec<_>>();
+	if !from_flags.is_empty() {
+		return from_flags;
+	}
+	// plural entries often only have the placeholder in the plural form, like "One file" and "%d files"
+	let has_placeholders = |kind| Some(&entry.msgid).into_iter().chain(entry.msgid_plural.iter())
+		.any(|s| !placeholders(kind, s).is_empty());
+	let mut res = Vec::new();
+	if !entry.has_flag("no-c-format") && has_placeholders(FormatKind::C) {
+		res.push(FormatKind::C);
+	}
+	if !entry.has_flag("no-python-brace-format") && has_placeholders(FormatKind::Brace) {
+		res.push(FormatKind::Brace);
+	}
+	return res;
+}
+
+struct Placeholder {
+	/// As written in the string
+	text: String,
+	/// Which argument it refers to
+	arg: String,
+	/// The type of the argument, if the syntax has types
+	conv: String,
+	/// Whether the argument is given by the order of placeholders
+	sequential: bool,
+}
+
+fn placeholder_regex(kind: FormatKind) -> Regex {
+	// The space flag is left out so that texts like "50% discount" don't count as placeholders
+	let printf_rest = r"[-+#0']*(?:\d+|\*)?(?:\.(?:\d+|\*))?((?:hh|h|ll|l|L|q|j|z|t|I64|I32)?[diouxXeEfFgGaAcspn%])";
+	return Regex::new(&match kind {
+		FormatKind::C => format!(r"%(?:(\d+)\$)?{}", printf_rest),
+		FormatKind::Python => format!(r"%(?:\((\w+)\))?{}", printf_rest),
+		FormatKind::Brace => r"\{\{|\}\}|\{([^{}:!]*)(?:![rsa])?(?::[^{}]*)?\}".to_string(),
+		FormatKind::Qt => r"%L?(\d{1,2})".to_string(),
+	}).unwrap();
+}
+
+fn placeholders(kind: FormatKind, s: &str) -> Vec<Placeholder> {
+	let mut res = Vec::new();
+	let mut seq_idx = 0;
+	for cap in placeholder_regex(kind).captures_iter(s) {
+		let text = cap.at(0).unwrap();
+		let conv = cap.at(2).unwrap_or("");
+		// escaped percent signs and braces
+		if conv == "%" || text == "{{" || text == "}}" {
+			continue;
+		}
+		let (arg, sequential) = match cap.at(1) {
+			Some(arg) if arg != "" => (arg.to_string(), false),
+			_ => {
+				seq_idx += 1;
+				(seq_idx.to_string(), true)
+			},
+		};
+		res.push(Placeholder {
+			text: text.to_string(),
+			arg: arg,
+			conv: conv.to_string(),
+			sequential: sequential,
+		});
+	}
+	return res;
+}
+
+fn list(phs: &[&Placeholder]) -> String {
+	return phs.iter().map(|p| p.text.as_ref()).collect::<Vec<&str>>().join(" ");
+}
+
+/// Compares the placeholders of msgid and msgstr, returning the problems found
+pub fn check(kind: FormatKind, msgid: &str, msgstr: &str) -> Vec<String> {
+	return check_placeholders(kind, msgid, msgstr, false);
+}
+
+/// Checks a plural form of a translation against msgid_plural, as every form
+/// may be used for numbers other than one. Forms that are only used for a
+/// single number may leave out placeholders, like msgfmt -c allows.
+pub fn check_plural(kind: FormatKind, msgid_plural: &str, msgstr: &str, single_number: bool) -> Vec<String> {
+	return check_placeholders(kind, msgid_plural, msgstr, single_number);
+}
+
+fn check_placeholders(kind: FormatKind, msgid: &str, msgstr: &str, allow_missing: bool) -> Vec<String> {
+	let mut res = Vec::new();
+	// Untranslated strings fall back to msgid at runtime
+	if msgstr == "" {
+		return res;
+	}
+	let id_phs = placeholders(kind, msgid);
+	let str_phs = placeholders(kind, msgstr);
+	let same = |a: &Placeholder, b: &Placeholder| a.arg == b.arg && a.conv == b.conv;
+
+	let all_sequential = id_phs.iter().chain(str_phs.iter()).all(|p| p.sequential);
+	if all_sequential && id_phs.len() == str_phs.len() && !id_phs.iter().zip(str_phs.iter()).all(|(a, b)| a.conv == b.conv) {
+		let mut id_convs = id_phs.iter().map(|p| &p.conv).collect::<Vec<_>>();
+		let mut str_convs = str_phs.iter().map(|p| &p.conv).collect::<Vec<_>>();
+		id_convs.sort();
+		str_convs.sort();
+		if id_convs == str_convs {
+			res.push(format!("{} placeholders in different order: '{}' in the original, '{}' in the translation",
+				kind.name(), list(&id_phs.iter().collect::<Vec<_>>()), list(&str_phs.iter().collect::<Vec<_>>())));
+			return res;
+		}
+	}
+
+	let missing = id_phs.iter().filter(|a| !str_phs.iter().any(|b| same(a, b))).collect::<Vec<_>>();
+	let extra = str_phs.iter().filter(|b| !id_phs.iter().any(|a| same(a, b))).collect::<Vec<_>>();
+	if !missing.is_empty() && !allow_missing {
+		res.push(format!("{} placeholders missing in the translation: {}", kind.name(), list(&missing)));
+	}
+	if !extra.is_empty() {
+		res.push(format!("{} placeholders not in the original: {}", kind.name(), list(&extra)));
+	}
+	return res;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use po;
+
+	fn texts(kind: FormatKind, s: &str) -> Vec<String> {
+		return placeholders(kind, s).into_iter().map(|p| p.text).collect();
+	}
+
+	#[test]
+	fn printf_placeholders() {
+		assert_eq!(texts(FormatKind::C, "%d%% of %-5.2f %1$s %lu"), vec!["%d", "%-5.2f", "%1$s", "%lu"]);
+		assert!(texts(FormatKind::C, "50% discount, 100%%").is_empty());
+		assert_eq!(texts(FormatKind::Python, "%(name)s has %d"), vec!["%(name)s", "%d"]);
+		assert_eq!(texts(FormatKind::Qt, "%1 of %L2"), vec!["%1", "%L2"]);
+	}
+
+	#[test]
+	fn brace_placeholders() {
+		assert_eq!(texts(FormatKind::Brace, "{{literal}} {0} {name!r:>10} {}"), vec!["{0}", "{name!r:>10}", "{}"]);
+		assert!(check(FormatKind::Brace, "{{x}} {0}", "{0} {{y}}").is_empty());
+		assert_eq!(check(FormatKind::Brace, "{0}", "{{0}}").len(), 1);
+	}
+
+	#[test]
+	fn check_mismatches() {
+		assert!(check(FormatKind::C, "%d files", "%d Dateien").is_empty());
+		assert!(check(FormatKind::C, "%d files", "").is_empty());
+		assert!(check(FormatKind::C, "100%% done", "100%% fertig").is_empty());
+		assert_eq!(check(FormatKind::C, "%s: %d", "%d: %s"),
+			vec!["printf placeholders in different order: '%s %d' in the original, '%d %s' in the translation"]);
+		assert!(check(FormatKind::C, "%1$s: %2$d", "%2$d: %1$s").is_empty());
+		assert_eq!(check(FormatKind::C, "%s of %s", "%s"),
+			vec!["printf placeholders missing in the translation: %s"]);
+		assert_eq!(check(FormatKind::C, "%s", "%s %x"),
+			vec!["printf placeholders not in the original: %x"]);
+		assert_eq!(check(FormatKind::Qt, "%1 and %2", "%1 und %3").len(), 2);
+	}
+
+	#[test]
+	fn check_plural_forms() {
+		assert!(check_plural(FormatKind::C, "%d files", "eine Datei", true).is_empty());
+		assert_eq!(check_plural(FormatKind::C, "%d files", "Dateien", false).len(), 1);
+		assert_eq!(check_plural(FormatKind::C, "%d files", "%s Datei", true),
+			vec!["printf placeholders not in the original: %s"]);
+	}
+
+	#[test]
+	fn kinds() {
+		let file = po::parse("#, python-format\nmsgid \"%(n)s\"\nmsgstr \"\"\n\nmsgid \"%s {0}\"\nmsgstr \"\"\n\n#, no-c-format\nmsgid \"%s\"\nmsgstr \"\"\n").unwrap();
+		assert_eq!(format_kinds(&file.entries[0]), vec![FormatKind::Python]);
+		assert_eq!(format_kinds(&file.entries[1]), vec![FormatKind::C, FormatKind::Brace]);
+		assert!(format_kinds(&file.entries[2]).is_empty());
+		let file = po::parse("msgid \"One file\"\nmsgid_plural \"%d files\"\nmsgstr[0] \"eine Datei\"\nmsgstr[1] \"viele\"\n").unwrap();
+		assert_eq!(format_kinds(&file.entries[0]), vec![FormatKind::C]);
+	}
+}
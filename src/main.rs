@@ -30,6 +30,7 @@ extern crate git2;
 extern crate url;
 extern crate regex;
 extern crate rustc_serialize;
+extern crate encoding;
 
 use std::env;
 use std::io;
@@ -37,62 +38,184 @@ use std::io::Read;
 use std::io::Write;
 use std::fs::{File};
 use git2::*;
-use std::str;
 use std::fmt;
 use std::fs;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::collections::btree_map::Entry;
 use std::path::Path;
-use std::ops::Deref;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use rustc_serialize::json::Json;
 use regex::Regex;
+use encoding::{DecoderTrap, EncodingRef};
+use encoding::label::encoding_from_whatwg_label;
 
 mod t6tor;
 use t6tor::*;
 
-macro_rules! otry {
-	($expr:expr) => (match $expr {
-		std::option::Option::Some(val) => val,
-		std::option::Option::None => {
-			panic!("NOOOO") //return std::result::Result::Err(std::convert::From::from(Error::from_str("Error! None given!")))
-		}
+mod po;
+use po::{PoFile, PoEntry};
+
+mod plural;
+use plural::PluralForms;
+
+mod format;
+
+mod worddiff;
+
+mod notes;
+
+// In gate mode stdout is reserved for the summary, so everything else goes to stderr
+static OUTPUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! say {
+	($($arg:tt)*) => (if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+		let _ = writeln!(&mut io::stderr(), $($arg)*);
+	} else {
+		println!($($arg)*);
 	})
 }
 
+const DEFAULT_TRANSLATION_CACHE: &'static str = "translation-cache.toml";
+const DEFAULT_TRANSLATION_CACHE_MAX_ENTRIES: usize = 10000;
+// in days
+const DEFAULT_TRANSLATION_CACHE_MAX_AGE: u64 = 30;
+
+// Exit statuses of gate mode
+const EXIT_ERROR: i32 = 1;
+const EXIT_REJECTED: i32 = 2;
+const EXIT_UNREVIEWED: i32 = 3;
+
 fn main() {
 	match run() {
-		Ok(val) =>(),
-		Err(err) => println!("Error: {}", err),
+		Ok(status) => process::exit(status),
+		Err(err) => {
+			say!("Error: {}", err);
+			process::exit(EXIT_ERROR);
+		},
 	}
 }
 
-fn run() -> Result<(), Error> {
-	let commit_identifier = env::args().nth(1).expect("No arg found");
-	println!("Checking commit identifier: {}", commit_identifier);
+/// Returns the exit status
+fn run() -> Result<i32, Error> {
+	let mut net_diff = false;
+	let mut merge_mode = None;
+	let mut base = None;
+	let mut uncommitted = None;
+	let mut upstream = None;
+	let mut per_commit = false;
+	let mut gate = false;
+	let mut clear_cache = false;
+	let mut commit_identifiers = Vec::new();
+	for arg in env::args().skip(1) {
+		match arg.as_ref() {
+			"--net" => net_diff = true,
+			"--merge=combined" => merge_mode = Some(MergeMode::Combined),
+			"--merge=base" => merge_mode = Some(MergeMode::Base),
+			"--merge=first-parent" => merge_mode = Some(MergeMode::FirstParent),
+			"--staged" => uncommitted = Some(UncommittedMode::Staged),
+			"--unstaged" => uncommitted = Some(UncommittedMode::Unstaged),
+			"--per-commit" => per_commit = true,
+			"--gate" => gate = true,
+			"--clear-translation-cache" => clear_cache = true,
+			opt if opt.starts_with("--upstream=") => upstream = Some(opt["--upstream=".len() ..].to_string()),
+			opt if opt.starts_with("--base=") => base = Some(opt["--base=".len() ..].to_string()),
+			opt if opt.starts_with("--") => return Err(Error::from_str(&format!("Unknown option {}", opt))),
+			_ => commit_identifiers.push(arg.clone()),
+		}
+	}
+	OUTPUT_TO_STDERR.store(gate, Ordering::Relaxed);
+	if clear_cache {
+		let settings = load_toml("settings.toml");
+		let path = settings.get("translation-cache").map_or(DEFAULT_TRANSLATION_CACHE, |s| s.as_str().unwrap());
+		try!(t6tor::clear_translation_cache(path)
+			.map_err(|e| Error::from_str(&format!("Could not remove translation cache {}: {}", path, e))));
+		say!("Cleared translation cache {}", path);
+		return Ok(0);
+	}
+	if uncommitted.is_some() && (net_diff || !commit_identifiers.is_empty()) {
+		return Err(Error::from_str("--staged and --unstaged take no commits"));
+	}
+	if upstream.is_some() && (net_diff || commit_identifiers.len() != 1) {
+		return Err(Error::from_str("--upstream needs exactly one branch"));
+	}
+	if per_commit && upstream.is_none() {
+		return Err(Error::from_str("--per-commit only works together with --upstream"));
+	}
+	if uncommitted.is_none() && commit_identifiers.is_empty() {
+		return Err(Error::from_str("No commit given"));
+	}
 	let settings = load_toml("settings.toml");
 	let repo = open_repo(settings.get("repo")
 		.expect("Could not find repo path setting")
 		.as_str().unwrap());
-	let diff = get_diff_for_commit(&repo, &commit_identifier).ok().expect("Diff loading failed");
 
-	let filenames = get_changed_filenames(&diff);
-	for st in filenames.ok().unwrap() {
-		println!("Seen file change: {}; ", st);
-	}
-
-	let translate_to = settings.get("translate-to").unwrap().as_str().unwrap();
-	let api_name = settings.get("translate-api").unwrap().as_str().unwrap();
-	let trans = match api_name.as_ref() {
-		"ms" => Box::new(t6tor::ms_translator(&settings, translate_to.to_string())) as Box<Translator>,
-		"yn" => Box::new(t6tor::yn_translator(&settings, translate_to.to_string())) as Box<Translator>,
-		_ => panic!("invalid API specifier for translate-to"),
+	// gate mode neither asks nor stores anything, so it has no use for translations
+	// and mustn't depend on the network or write the translation cache
+	let cached_trans = if gate {
+		None
+	} else {
+		let translate_to = settings.get("translate-to").unwrap().as_str().unwrap();
+		let api_name = settings.get("translate-api").unwrap().as_str().unwrap();
+		let trans = match api_name.as_ref() {
+			"ms" => Box::new(t6tor::ms_translator(&settings, translate_to.to_string())) as Box<Translator>,
+			"yn" => Box::new(t6tor::yn_translator(&settings, translate_to.to_string())) as Box<Translator>,
+			_ => panic!("invalid API specifier for translate-to"),
+		};
+		Some(t6tor::CachedTranslator::new(trans, api_name, translate_to,
+			settings.get("translation-cache").map_or(DEFAULT_TRANSLATION_CACHE, |s| s.as_str().unwrap()),
+			settings.get("translation-cache-max-entries").map_or(DEFAULT_TRANSLATION_CACHE_MAX_ENTRIES,
+				|v| v.as_integer().expect("translation-cache-max-entries must be an integer") as usize),
+			settings.get("translation-cache-max-age").map_or(DEFAULT_TRANSLATION_CACHE_MAX_AGE,
+				|v| v.as_integer().expect("translation-cache-max-age must be an integer") as u64) * 24 * 60 * 60))
+	};
+	let no_trans = NoTranslator;
+	let trans = match cached_trans {
+		Some(ref cached_trans) => cached_trans as &Translator,
+		None => &no_trans as &Translator,
 	};
 
 	if let Some(attri) = trans.attribution_info() {
-		println!("\n{}\n", attri);
+		say!("\n{}\n", attri);
+	}
+	let mut review_settings = load_review_settings(&settings);
+	if let Some(merge_mode) = merge_mode {
+		review_settings.merge_mode = merge_mode;
 	}
-	let regex_opt = settings.get("filename-regex").map_or(None, |s| s.as_str());
+	review_settings.base = base.as_ref().map(|b| b.as_ref());
+	review_settings.interactive = !gate;
 
-	let subjects = try!(get_subjects_for_commit(&commit_identifier, &repo, trans.deref(), regex_opt));
+	let mut subjects = if let Some(mode) = uncommitted {
+		try!(get_subjects_for_uncommitted(mode, &repo, trans, &review_settings))
+	} else if let Some(ref upstream) = upstream {
+		try!(get_subjects_for_branch(&commit_identifiers[0], upstream, per_commit, &repo, trans, &review_settings))
+	} else if net_diff {
+		// review the difference between the two trees at once
+		let range = match (commit_identifiers.len(), split_range(&commit_identifiers[0])) {
+			(1, Some(range)) => range,
+			_ => return Err(Error::from_str("--net needs exactly one A..B range")),
+		};
+		let (from, to) = (try!(resolve_commit(&repo, range.0)), try!(resolve_commit(&repo, range.1)));
+		say!("Checking net changes of range: {}..{}", from, to);
+		try!(get_subjects_for_range(&from, &to, &repo, trans, &review_settings))
+	} else {
+		let mut commits = Vec::new();
+		for commit_identifier in commit_identifiers.iter() {
+			match split_range(commit_identifier) {
+				Some((from, to)) => commits.extend(try!(get_commits_in_range(&repo,
+					&try!(resolve_commit(&repo, from)), &try!(resolve_commit(&repo, to))))),
+				None => commits.push(try!(resolve_commit(&repo, commit_identifier))),
+			}
+		}
+		let mut subjects = Vec::new();
+		for commit_identifier in commits {
+			say!("Checking commit identifier: {}", commit_identifier);
+			subjects.extend(try!(get_subjects_for_commit(&commit_identifier, &repo, trans, &review_settings)));
+		}
+		subjects
+	};
 
 	//let answer_filename = format!("answers.{}.toml", commit_identifier);
 	let answer_filename = "answers.toml";
@@ -102,13 +225,443 @@ fn run() -> Result<(), Error> {
 	};
 	let mut answers = if exists {
 		load_toml(answer_filename) } else { toml::Table::new() };
-	conduct_asking(subjects, &mut answers, true);
-	save_toml(answer_filename, answers);
+	// All translating is done now. Save the cache already, as reviews
+	// often get ended with Ctrl-C, which would skip saving it on drop.
+	if let Some(ref cached_trans) = cached_trans {
+		if let Err(e) = cached_trans.save() {
+			say!("Failed to write translation cache: {}", e);
+		}
+	}
+	set_subject_people(&repo, &mut subjects);
+	let notes_ref = settings.get("notes-ref").map_or(None, |s| s.as_str());
+	let reviewed_commits = group_subject_ids_by_commit(&subjects);
+	let subject_ids = subjects.iter().map(|qs| qs.get_subject_id()).collect::<Vec<_>>();
+	if let Some(notes_ref) = notes_ref {
+		load_notes(&repo, notes_ref, &reviewed_commits, &mut answers);
+	}
+	conduct_asking(subjects, &mut answers, !gate, &review_settings, &repo);
+	// gate mode only evaluates, it must not write to the repo or the working directory
+	if !gate {
+		// save locally first, so that answers don't get lost if writing notes fails
+		save_toml(answer_filename, &answers);
+	}
+	if let (Some(notes_ref), false) = (notes_ref, gate) {
+		if let Err(e) = save_notes(&repo, notes_ref, &reviewed_commits, &answers) {
+			say!("Could not store the review in {}: {}", notes_ref, e);
+			say!("The answers are still saved in {}", answer_filename);
+		}
+	}
+	say!("Finished!");
+	// the summary is the last line of the output, for easy parsing
+	let status = if gate { print_gate_summary(&subject_ids, &answers) } else { 0 };
+	return Ok(status);
+}
+
+/// Prints the verdicts as JSON on a single line and returns the exit status for gate mode
+fn print_gate_summary(subject_ids: &[String], answers: &toml::Table) -> i32 {
+	let mut approved = Vec::new();
+	let mut rejected = Vec::new();
+	let mut unreviewed = Vec::new();
+	for subj_id in subject_ids {
+		let list = match answers.get(subj_id).and_then(|v| v.as_bool()) {
+			Some(true) => &mut approved,
+			Some(false) => &mut rejected,
+			None => &mut unreviewed,
+		};
+		list.push(Json::String(subj_id.clone()));
+	}
+	let (result, status) = if !rejected.is_empty() {
+		("rejected", EXIT_REJECTED)
+	} else if !unreviewed.is_empty() {
+		("unreviewed", EXIT_UNREVIEWED)
+	} else {
+		("approved", 0)
+	};
+	let mut summary = BTreeMap::new();
+	summary.insert("result".to_string(), Json::String(result.to_string()));
+	summary.insert("approved".to_string(), Json::U64(approved.len() as u64));
+	summary.insert("rejected".to_string(), Json::Array(rejected));
+	summary.insert("unreviewed".to_string(), Json::Array(unreviewed));
+	println!("{}", Json::Object(summary));
+	return status;
+}
+
+fn format_signature(sig: &Signature) -> String {
+	return format!("{} <{}>", sig.name().unwrap_or("<unknown>"), sig.email().unwrap_or("<unknown>"));
+}
+
+/// Fills in who authored and committed the commit of each subject.
+/// Subjects for ranges or uncommitted changes have no single commit and are left alone.
+fn set_subject_people(repo: &Repository, subjects: &mut [QuestionSubject]) {
+	for qs in subjects.iter_mut() {
+		let commit = match Oid::from_str(&qs.commit_id).ok().and_then(|id| repo.find_commit(id).ok()) {
+			Some(commit) => commit,
+			None => continue,
+		};
+		let author = commit.author();
+		qs.author = Some(format_signature(&author));
+		qs.author_email = author.email().map(|e| e.to_string());
+		let committer = commit.committer();
+		qs.committer = Some(format_signature(&committer));
+		qs.committer_email = committer.email().map(|e| e.to_string());
+	}
+}
+
+// How many earlier translations the history view shows at most
+const MAX_HISTORY: usize = 10;
+
+/// Formats a commit time as date in UTC, like "2015-08-23"
+fn format_date(time: Time) -> String {
+	// from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let secs = time.seconds();
+	let days = secs / 86400 - if secs < 0 && secs % 86400 != 0 { 1 } else { 0 };
+	let z = days + 719468;
+	let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+	return format!("{:04}-{:02}-{:02}", y, m, d);
+}
+
+// How many commits changing a po file to look at at most
+const MAX_FILE_CHANGES: usize = 500;
+
+/// A commit that changed a po file
+struct FileChange {
+	commit_id: Oid,
+	date: String,
+	author: String,
+	blob_id: Oid,
+	parent_blob_id: Option<Oid>,
+}
 
-	println!("Finished!");
+/// Gives access to earlier versions of po files. Both the log walks
+/// and the parsed files are cached, as many entries of the same file get looked up.
+struct History<'repo> {
+	repo: &'repo Repository,
+	/// Commits changing a file, most recent first, by start commit and file name
+	changes: RefCell<HashMap<(String, String), Rc<Vec<FileChange>>>>,
+	/// Parsed po files by blob id, None if they couldn't be parsed
+	files: RefCell<HashMap<Oid, Rc<Option<PoFile>>>>,
+}
+
+impl<'repo> History<'repo> {
+	fn new(repo: &'repo Repository) -> History<'repo> {
+		return History {
+			repo: repo,
+			changes: RefCell::new(HashMap::new()),
+			files: RefCell::new(HashMap::new()),
+		};
+	}
+
+	/// Returns the commits that changed the file before the given change,
+	/// which may be a commit id, a range or uncommitted content.
+	fn file_changes(&self, change_id: &str, fname: &str) -> Result<Rc<Vec<FileChange>>, Error> {
+		let key = (change_id.to_string(), fname.to_string());
+		if let Some(changes) = self.changes.borrow().get(&key) {
+			return Ok(changes.clone());
+		}
+		let mut walk = try!(self.repo.revwalk());
+		walk.set_sorting(SORT_TOPOLOGICAL | SORT_TIME);
+		// start before the reviewed change
+		let own_id = Oid::from_str(change_id).ok();
+		match (own_id, split_range(change_id)) {
+			(Some(id), _) => try!(walk.push(id)),
+			(None, Some((from, _))) => try!(walk.push(try!(Oid::from_str(from)))),
+			(None, None) => try!(walk.push_head()),
+		}
+		let mut res = Vec::new();
+		for oid in walk {
+			if Some(oid) == own_id {
+				continue;
+			}
+			let commit = try!(self.repo.find_commit(oid));
+			let blob_id = match try!(commit.tree()).get_path(Path::new(fname)) {
+				Ok(entry) => entry.id(),
+				Err(_) => continue,
+			};
+			let parent_blob_id = match commit.parent(0) {
+				Ok(parent) => try!(parent.tree()).get_path(Path::new(fname)).ok().map(|e| e.id()),
+				Err(_) => None,
+			};
+			if Some(blob_id) == parent_blob_id {
+				// the file didn't change in this commit
+				continue;
+			}
+			res.push(FileChange {
+				commit_id: oid,
+				date: format_date(commit.time()),
+				author: format_signature(&commit.author()),
+				blob_id: blob_id,
+				parent_blob_id: parent_blob_id,
+			});
+			if res.len() == MAX_FILE_CHANGES {
+				break;
+			}
+		}
+		let res = Rc::new(res);
+		self.changes.borrow_mut().insert(key, res.clone());
+		return Ok(res);
+	}
+
+	fn parsed_file(&self, blob_id: Oid, fname: &str) -> Rc<Option<PoFile>> {
+		if let Some(po) = self.files.borrow().get(&blob_id) {
+			return po.clone();
+		}
+		let po = self.repo.find_blob(blob_id).ok()
+			.and_then(|blob| blob_parser(blob.content(), fname).ok());
+		let po = Rc::new(po);
+		self.files.borrow_mut().insert(blob_id, po.clone());
+		return po;
+	}
+
+	/// Returns the translation of the subject's entry in the given version of its file
+	fn translation(&self, blob_id: Oid, qs: &QuestionSubject) -> Option<String> {
+		let po = self.parsed_file(blob_id, &qs.from_filename);
+		return po.as_ref().as_ref()
+			.and_then(|po| po.find(qs.entry.msgctxt.as_ref().map(|c| c.as_ref()), &qs.entry.msgid))
+			.and_then(|e| e.msgstr.get(qs.plural_index.unwrap_or(0)).cloned());
+	}
+}
+
+/// An earlier translation of an entry
+struct HistoryItem {
+	commit_id: Oid,
+	date: String,
+	author: String,
+	value: String,
+}
+
+/// Returns the earlier translations of the subject's entry, most recent first.
+/// At most limit items are returned.
+fn entry_history(qs: &QuestionSubject, history: &History, limit: usize) -> Result<Vec<HistoryItem>, Error> {
+	let mut res = Vec::new();
+	for change in try!(history.file_changes(&qs.commit_id, &qs.from_filename)).iter() {
+		let val = match history.translation(change.blob_id, qs) {
+			Some(val) => val,
+			None => continue,
+		};
+		if change.parent_blob_id.and_then(|id| history.translation(id, qs)).as_ref() == Some(&val) {
+			continue;
+		}
+		res.push(HistoryItem {
+			commit_id: change.commit_id,
+			date: change.date.clone(),
+			author: change.author.clone(),
+			value: val,
+		});
+		if res.len() == limit {
+			break;
+		}
+	}
+	return Ok(res);
+}
+
+/// Returns the verdict recorded for an earlier translation of the subject's entry
+fn history_verdict(qs: &QuestionSubject, item: &HistoryItem, answers: &toml::Table) -> Option<bool> {
+	let subj_id = qs.get_subject_id_for_change(&format!("{}", item.commit_id));
+	return answers.get(&subj_id).and_then(|v| v.as_bool());
+}
+
+/// Prints the earlier translations of the subject's entry, along with any verdicts recorded for them
+fn print_history(qs: &QuestionSubject, history: &History, answers: &toml::Table) -> Result<(), Error> {
+	say!("Earlier translations of '{}' in {}:", po::visible(&qs.orig), qs.from_filename);
+	let items = try!(entry_history(qs, history, MAX_HISTORY + 1));
+	for item in items.iter().take(MAX_HISTORY) {
+		let verdict = match history_verdict(qs, item, answers) {
+			Some(true) => "approved",
+			Some(false) => "rejected",
+			None => "not reviewed",
+		};
+		say!("{} {} {} ({}): '{}'", &format!("{}", item.commit_id)[.. 8], item.date,
+			item.author, verdict, po::visible(&item.value));
+	}
+	if items.len() > MAX_HISTORY {
+		say!("...");
+	}
+	if items.is_empty() {
+		say!("No earlier translations found");
+	}
 	return Ok(());
 }
 
+// How many earlier translations to look at when checking for reverts
+const MAX_REVERT_SEARCH: usize = 50;
+
+/// Looks for an earlier translation of the entry that equals the new one and got a verdict.
+/// Returns the commit and verdict of the most recent one.
+fn find_earlier_verdict(qs: &QuestionSubject, history: &History, answers: &toml::Table) -> Option<(Oid, bool)> {
+	match qs.kind {
+		SubjectKind::Translation | SubjectKind::Unobsoleted => (),
+		_ => return None,
+	}
+	// only look at the history if the entry was answered before
+	let id_suffix = qs.get_subject_id_for_change("");
+	if !answers.keys().any(|k| k.ends_with(&id_suffix)) {
+		return None;
+	}
+	let items = match entry_history(qs, history, MAX_REVERT_SEARCH) {
+		Ok(items) => items,
+		Err(_) => return None,
+	};
+	for item in items.iter().filter(|item| item.value == qs.new) {
+		if let Some(verdict) = history_verdict(qs, item, answers) {
+			return Some((item.commit_id, verdict));
+		}
+	}
+	return None;
+}
+
+/// Returns the ids of the subjects for each reviewed commit, in review order.
+/// Ranges and uncommitted changes are left out, as there is no commit to attach notes to.
+fn group_subject_ids_by_commit(subjects: &[QuestionSubject]) -> Vec<(Oid, Vec<String>)> {
+	let mut res: Vec<(Oid, Vec<String>)> = Vec::new();
+	for qs in subjects {
+		let commit_id = match Oid::from_str(&qs.commit_id) {
+			Ok(commit_id) => commit_id,
+			Err(_) => continue,
+		};
+		let subj_id = qs.get_subject_id();
+		if let Some(&mut (_, ref mut ids)) = res.iter_mut().find(|&&mut (id, _)| id == commit_id) {
+			ids.push(subj_id);
+			continue;
+		}
+		res.push((commit_id, vec![subj_id]));
+	}
+	return res;
+}
+
+/// Adds verdicts stored in git notes to the answers, if not already in there
+fn load_notes(repo: &Repository, notes_ref: &str, commits: &[(Oid, Vec<String>)], answers: &mut toml::Table) {
+	for &(commit_id, _) in commits {
+		// the ref is shared, so a broken note shouldn't stop the review
+		let verdicts = match notes::read_verdicts(repo, notes_ref, commit_id) {
+			Ok(verdicts) => verdicts,
+			Err(e) => {
+				say!("Warning: {}, ignoring it", e);
+				continue;
+			},
+		};
+		for (subj_id, verdict) in verdicts {
+			if let Entry::Vacant(e) = answers.entry(subj_id) {
+				e.insert(verdict);
+			}
+		}
+	}
+}
+
+/// Writes the verdicts for each commit into a git note.
+/// A commit is approved if all of its subjects were answered with Ok.
+fn save_notes(repo: &Repository, notes_ref: &str, commits: &[(Oid, Vec<String>)], answers: &toml::Table) -> Result<(), Error> {
+	for &(commit_id, ref subj_ids) in commits {
+		let mut verdicts = toml::Table::new();
+		let mut approved = true;
+		for subj_id in subj_ids {
+			match answers.get(subj_id) {
+				Some(verdict) => {
+					approved &= verdict.as_bool().unwrap_or(false);
+					verdicts.insert(subj_id.clone(), verdict.clone());
+				},
+				None => approved = false,
+			}
+		}
+		try!(notes::write_verdicts(repo, notes_ref, commit_id, approved, verdicts));
+		say!("Stored review of {} in {} ({})", commit_id, notes_ref,
+			if approved { "approved" } else { "not approved" });
+	}
+	return Ok(());
+}
+
+/// What to do with entries marked fuzzy
+#[derive(Clone, Copy, PartialEq)]
+enum FuzzyPolicy {
+	Review,
+	Skip,
+	Accept,
+}
+
+/// What to diff merge commits against
+#[derive(Clone, Copy, PartialEq)]
+enum MergeMode {
+	/// Only changes not present in any of the parents, like git's combined diff
+	Combined,
+	/// All changes relative to the merge base of the parents
+	Base,
+	/// All changes relative to the first parent
+	FirstParent,
+}
+
+/// Which uncommitted changes to review
+#[derive(Clone, Copy, PartialEq)]
+enum UncommittedMode {
+	/// Changes from HEAD to the index
+	Staged,
+	/// Changes from the index to the working directory
+	Unstaged,
+}
+
+/// How to treat changes by a given author
+#[derive(Clone, Copy, PartialEq)]
+enum TrustPolicy {
+	/// Accept the changes without asking
+	Accept,
+	/// Always ask, even if the change could be accepted automatically
+	Review,
+	/// Reject the changes without asking
+	Reject,
+}
+
+/// Settings that influence which subjects get created and how they get asked
+struct ReviewSettings<'a> {
+	filename_regex: Option<&'a str>,
+	fuzzy_policy: FuzzyPolicy,
+	reject_format_mismatches: bool,
+	/// Path of the .pot file in the repo
+	template: Option<&'a str>,
+	merge_mode: MergeMode,
+	/// What to review against if a commit has no (available) parent
+	base: Option<&'a str>,
+	/// Policies for author emails, in lowercase
+	trust: BTreeMap<String, TrustPolicy>,
+	/// Whether to ask the user, false in gate mode
+	interactive: bool,
+}
+
+fn load_review_settings(settings: &toml::Table) -> ReviewSettings {
+	let fuzzy_policy = match settings.get("fuzzy-policy").map_or(None, |s| s.as_str()) {
+		None | Some("review") => FuzzyPolicy::Review,
+		Some("skip") => FuzzyPolicy::Skip,
+		Some("accept") => FuzzyPolicy::Accept,
+		Some(_) => panic!("invalid value for fuzzy-policy"),
+	};
+	let mut trust = BTreeMap::new();
+	if let Some(tbl) = settings.get("trust") {
+		for (email, policy) in tbl.as_table().expect("trust must be a table") {
+			trust.insert(email.to_lowercase(), match policy.as_str() {
+				Some("accept") => TrustPolicy::Accept,
+				Some("review") => TrustPolicy::Review,
+				Some("reject") => TrustPolicy::Reject,
+				_ => panic!("invalid trust policy for {}", email),
+			});
+		}
+	}
+	return ReviewSettings {
+		filename_regex: settings.get("filename-regex").map_or(None, |s| s.as_str()),
+		fuzzy_policy: fuzzy_policy,
+		reject_format_mismatches: settings.get("reject-format-mismatches")
+			.map_or(false, |v| v.as_bool().expect("reject-format-mismatches must be a boolean")),
+		template: settings.get("template").map_or(None, |s| s.as_str()),
+		merge_mode: MergeMode::Combined,
+		base: None,
+		trust: trust,
+		interactive: true,
+	};
+}
+
 fn open_repo(path: &str) -> Repository {
 	return match Repository::open(path) {
 		Ok(repo) => repo,
@@ -131,11 +684,11 @@ fn load_toml(path: &str) -> toml::Table {
 	}
 }
 
-fn save_toml(path: &str, tbl: toml::Table) {
+fn save_toml(path: &str, tbl: &toml::Table) {
 	let mut f = File::create(path)
 		.ok()
 		.expect(&format!("Failed to open toml file '{}'", path));
-	f.write_all(toml::Value::Table(tbl).to_string().as_bytes())
+	f.write_all(toml::Value::Table(tbl.clone()).to_string().as_bytes())
 		.ok()
 		.expect(&format!("Failed to write toml file '{}'", path));
 }
@@ -147,27 +700,170 @@ enum PDDesc {
 	Later,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum SubjectKind {
+	Translation,
+	Header,
+	/// An entry that got commented out with "#~"
+	Obsoleted,
+	/// An entry that was commented out with "#~" before
+	Unobsoleted,
+	/// The file was renamed, old holds the old path
+	Rename,
+	/// The file was copied, old holds the path of the source
+	Copy,
+}
+
+impl Default for SubjectKind {
+	fn default() -> SubjectKind {
+		return SubjectKind::Translation;
+	}
+}
+
 #[derive(Default)]
 struct QuestionSubject {
+	kind: SubjectKind,
 	commit_id: String,
 	from_filename: String,
 	orig: String,
+	/// The original before the source text changed, if it did
+	previous_orig: Option<String>,
 	old: Option<String>,
 	new: String,
 	oldtrans: String,
 	newtrans: String,
+	entry: PoEntry,
+	old_entry: Option<PoEntry>,
+	plural_index: Option<usize>,
+	plural_rule: Option<String>,
+	warnings: Vec<String>,
+	/// Placeholders missing, added or reordered in the translation
+	format_problems: Vec<String>,
+	/// Lines around the source references of the entry
+	source_snippets: Vec<String>,
+	/// The entry from the template, if a template is configured
+	template_entry: Option<PoEntry>,
+	/// Author and committer of the commit, like "Name <email>"
+	author: Option<String>,
+	author_email: Option<String>,
+	committer: Option<String>,
+	committer_email: Option<String>,
+	/// Language of the po file, if detected
+	from_lang: Option<String>,
+}
+
+fn print_entry_context(qs: &QuestionSubject) {
+	for comment in qs.entry.extracted_comments.iter() {
+		say!("Comment for translators: {}", comment);
+	}
+	if let Some(ref template_entry) = qs.template_entry {
+		// the po file may not be up to date with the template
+		for comment in template_entry.extracted_comments.iter() {
+			if !qs.entry.extracted_comments.contains(comment) {
+				say!("Comment for translators (template): {}", comment);
+			}
+		}
+	}
+	for comment in qs.entry.translator_comments.iter() {
+		say!("Translator comment: {}", comment);
+	}
+	if !qs.entry.references.is_empty() {
+		say!("Used at: {}", qs.entry.references.join(", "));
+	}
+	for snippet in qs.source_snippets.iter() {
+		say!("{}", snippet);
+	}
 }
 
-fn askq(qs: &QuestionSubject) -> PDDesc {
+fn askq(qs: &QuestionSubject, history: &History, answers: &toml::Table) -> PDDesc {
 	let no_available_str = "<no old version available>".to_string();
-	println!("Original: '{}'\n\nOld: {}\nNew: {}\n\nOld translated: {}\nNew translated: {}",
-		qs.orig, match qs.old { Some(ref v)=>v, None=>&no_available_str },
-		qs.new, qs.oldtrans, qs.newtrans);
+	match qs.kind {
+		SubjectKind::Translation | SubjectKind::Unobsoleted => {
+			if qs.kind == SubjectKind::Unobsoleted {
+				say!("The entry was restored from an obsolete (#~) entry");
+			}
+			if let Some(ref ctxt) = qs.entry.msgctxt {
+				say!("Context: '{}'", po::visible(ctxt));
+			}
+			print_entry_context(qs);
+			if let Some(ref previous_orig) = qs.previous_orig {
+				say!("Source text changed from: '{}'\nSource text diff: {}",
+					po::visible(previous_orig), po::visible(&worddiff::word_diff(previous_orig, &qs.orig)));
+			}
+			if !qs.entry.flags.is_empty() {
+				say!("Flags: {}", qs.entry.flags.join(", "));
+			}
+			if let Some(ref old_entry) = qs.old_entry {
+				match (old_entry.has_flag("fuzzy"), qs.entry.has_flag("fuzzy")) {
+					(false, true) => say!("The entry was marked fuzzy"),
+					(true, false) => say!("The entry is not fuzzy anymore"),
+					_ => (),
+				}
+			}
+			say!("Original: '{}'\n\nOld: {}\nNew: {}\n\nOld translated: {}\nNew translated: {}",
+				po::visible(&qs.orig), po::visible(match qs.old { Some(ref v)=>v, None=>&no_available_str }),
+				po::visible(&qs.new), po::visible(&qs.oldtrans), po::visible(&qs.newtrans));
+			if let Some(ref rule) = qs.plural_rule {
+				say!("Plural {}", rule);
+			}
+		},
+		SubjectKind::Obsoleted => {
+			say!("The entry became obsolete (#~)");
+			if let Some(ref ctxt) = qs.entry.msgctxt {
+				say!("Context: '{}'", po::visible(ctxt));
+			}
+			say!("Original: '{}'\n\nLast translation: {}\nLast translation translated: {}",
+				po::visible(&qs.orig), po::visible(match qs.old { Some(ref v)=>v, None=>&no_available_str }),
+				po::visible(&qs.oldtrans));
+		},
+		SubjectKind::Rename | SubjectKind::Copy => {
+			say!("{} was {} from {}", qs.from_filename,
+				if qs.kind == SubjectKind::Rename { "renamed" } else { "copied" },
+				match qs.old { Some(ref v)=>v, None=>&no_available_str });
+		},
+		SubjectKind::Header => {
+			say!("Header of {} changed:", qs.from_filename);
+			for line in header_diff(qs.old_entry.as_ref(), &qs.entry) {
+				say!("{}", line);
+			}
+		},
+	}
+	if let Some(ref author) = qs.author {
+		say!("Author: {}", author);
+	}
+	if let Some(ref committer) = qs.committer {
+		say!("Committer: {}", committer);
+	}
+	for warning in qs.warnings.iter() {
+		say!("Warning: {}", warning);
+	}
+	for problem in qs.format_problems.iter() {
+		say!("Warning: {}", problem);
+	}
+
+	let has_history = match qs.kind {
+		SubjectKind::Translation | SubjectKind::Unobsoleted => {
+			say!("(Answer h to see earlier translations of the entry)");
+			true
+		},
+		_ => false,
+	};
 
 	let mut answ = String::from("Your answer: ");
-	io::stdin().read_line(&mut answ)
-		.ok()
-		.expect("Failed to read line");
+	loop {
+		let mut input = String::new();
+		io::stdin().read_line(&mut input)
+			.ok()
+			.expect("Failed to read line");
+		if has_history && input.trim() == "h" {
+			if let Err(e) = print_history(qs, history, answers) {
+				say!("Could not read history: {}", e);
+			}
+			continue;
+		}
+		answ.push_str(&input);
+		break;
+	}
 	let mut resp = PDDesc::NoValid;
 	for x in answ.chars() {
 		resp = match x {
@@ -181,6 +877,10 @@ fn askq(qs: &QuestionSubject) -> PDDesc {
 }
 
 fn is_obviously_equal(qs: &QuestionSubject) -> bool {
+	match qs.kind {
+		SubjectKind::Translation | SubjectKind::Unobsoleted => (),
+		_ => return false,
+	}
 	if qs.newtrans.to_lowercase() == qs.orig.to_lowercase() {
 		return true;
 	}
@@ -188,28 +888,153 @@ fn is_obviously_equal(qs: &QuestionSubject) -> bool {
 	return false;
 }
 
+// Used as commit id for changes that are not committed yet
+const UNCOMMITTED_ID: &'static str = "<uncommitted>";
+
 impl QuestionSubject {
+	/// Fills in the translations of the old and new values
+	fn translate(&mut self, trans: &Translator) {
+		let from_lang = self.from_lang.as_ref().map(|l| l.as_ref());
+		match self.kind {
+			SubjectKind::Translation | SubjectKind::Unobsoleted => {
+				if let Some(ref old) = self.old {
+					self.oldtrans = trans.translate(old, from_lang);
+				}
+				self.newtrans = trans.translate(&self.new, from_lang);
+			},
+			SubjectKind::Obsoleted => if let Some(ref old) = self.old {
+				self.oldtrans = trans.translate(old, from_lang);
+			},
+			_ => (),
+		}
+	}
+
+	/// The commit part of the subject id. Uncommitted changes have no
+	/// commit id yet, so they are identified by their content instead.
+	fn get_change_id(&self) -> String {
+		if self.commit_id != UNCOMMITTED_ID {
+			return self.commit_id.clone();
+		}
+		// The content itself is part of the id, as a hash of it could be forged to collide
+		let old = match self.old {
+			Some(ref old) => format!("\"{}\"", po::escape(old)),
+			None => "none".to_string(),
+		};
+		return format!("uncommitted {} -> \"{}\" #, {}", old, po::escape(&self.new), self.entry.flags.join(", "));
+	}
+
 	fn get_subject_id(&self) -> String {
-		return format!("{}:{}:{}", self.commit_id, self.from_filename, self.orig);
+		return self.get_subject_id_for_change(&self.get_change_id());
+	}
+
+	/// The id the subject would have if it was part of the given change
+	fn get_subject_id_for_change(&self, change_id: &str) -> String {
+		// These contain plain quotes, which the escaped msgids below can't contain
+		match self.kind {
+			SubjectKind::Header => return format!("{}:{}:msgid \"\" (header)", change_id, self.from_filename),
+			SubjectKind::Rename | SubjectKind::Copy => return format!("{}:{}:{} from \"{}\"",
+				change_id, self.from_filename,
+				if self.kind == SubjectKind::Rename { "renamed" } else { "copied" },
+				self.old.as_ref().unwrap()),
+			_ => (),
+		}
+		// Use the escaped form, with newlines written like older versions did,
+		// so that existing answer files stay valid.
+		let mut orig_key = po::escape(&self.orig).replace("\\n", " | ");
+		// Plural forms are quoted like in the po file. As escaped strings can't
+		// contain a plain quote, they can't collide with the key of any msgid.
+		if let Some(idx) = self.plural_index {
+			orig_key = format!("msgstr[{}] \"{}\"", idx, po::escape(&self.orig));
+		}
+		// Entries without context keep the plain msgid as key
+		if let Some(ref ctxt) = self.entry.msgctxt {
+			orig_key = format!("msgctxt \"{}\" {}", po::escape(ctxt), orig_key);
+		}
+		return format!("{}:{}:{}", change_id, self.from_filename, orig_key);
 	}
 }
 
-fn conduct_asking(qsl: Vec<QuestionSubject>, answ: &mut toml::Table, reask_non_ok: bool) {
+/// Asks about the subject, or leaves it unreviewed if we may not ask
+fn ask_if_interactive(qs: &QuestionSubject, history: &History, answers: &toml::Table, settings: &ReviewSettings) -> PDDesc {
+	if !settings.interactive {
+		say!("Not asking about string '{}' in gate mode (ID {}).", po::visible(&qs.orig), qs.get_subject_id());
+		return PDDesc::Later;
+	}
+	return askq(qs, history, answers);
+}
+
+fn conduct_asking(qsl: Vec<QuestionSubject>, answ: &mut toml::Table, reask_non_ok: bool, settings: &ReviewSettings, repo: &Repository) {
+	// for the history view, which can't access answ while we are changing it
+	let known_answers = answ.clone();
+	let history = History::new(repo);
 	let mut ok_old_ctr = 0;
 	let mut notok_old_ctr = 0;
 	let mut ok_new_ctr = 0;
 	let mut notok_new_ctr = 0;
 	let mut ignored_ctr = 0;
+	let mut obsoleted_ctr = 0;
+	let mut unobsoleted_ctr = 0;
+	let mut restored_ctr = 0;
+	let mut reintroduced_ctr = 0;
 
-	for qu in qsl {
+	for mut qu in qsl {
+		match qu.kind {
+			SubjectKind::Obsoleted => obsoleted_ctr += 1,
+			SubjectKind::Unobsoleted => unobsoleted_ctr += 1,
+			_ => (),
+		}
 		let subj_id = qu.get_subject_id();
+		let trust_for = |email: &Option<String>| email.as_ref().and_then(|e| settings.trust.get(&e.to_lowercase())).cloned();
+		let mut trust = trust_for(&qu.author_email);
+		// Author emails are easy to fake, so only skip the review if the committer is trusted too
+		if trust == Some(TrustPolicy::Accept) && trust_for(&qu.committer_email) != Some(TrustPolicy::Accept) {
+			trust = None;
+		}
+		// recognize reverts to earlier reviewed translations
+		let earlier_verdict = if answ.contains_key(&subj_id) { None } else { find_earlier_verdict(&qu, &history, &known_answers) };
+		// known-bad content must always be looked at by a human
+		let reintroduced = match earlier_verdict { Some((_, false)) => true, _ => false };
+		let auto_accept = trust != Some(TrustPolicy::Review) && !reintroduced;
+		match earlier_verdict {
+			Some((commit_id, true)) => qu.warnings.push(format!("restores the translation approved in commit {}", commit_id)),
+			Some((commit_id, false)) => {
+				qu.warnings.push(format!("RE-INTRODUCES the translation rejected in commit {}", commit_id));
+				reintroduced_ctr += 1;
+			},
+			None => (),
+		}
 		match answ.entry(subj_id.clone()) {
-			Entry::Vacant(e) => if is_obviously_equal(&qu) {
-				println!("Fast-forwarding string '{}' because equal according to translator (ID {}).", qu.orig, subj_id);
+			Entry::Vacant(e) => if trust == Some(TrustPolicy::Reject) {
+				say!("Rejecting string '{}' because its author {} is not trusted (ID {}).",
+					po::visible(&qu.orig), qu.author_email.as_ref().unwrap(), subj_id);
+				e.insert(toml::Value::Boolean(false));
+				notok_new_ctr += 1;
+			} else if settings.reject_format_mismatches && !qu.format_problems.is_empty() {
+				say!("Rejecting string '{}' because of placeholder problems: {} (ID {}).",
+					po::visible(&qu.orig), qu.format_problems.join("; "), subj_id);
+				e.insert(toml::Value::Boolean(false));
+				notok_new_ctr += 1;
+			} else if auto_accept && trust == Some(TrustPolicy::Accept) {
+				say!("Accepting string '{}' because its author {} is trusted (ID {}).",
+					po::visible(&qu.orig), qu.author_email.as_ref().unwrap(), subj_id);
+				e.insert(toml::Value::Boolean(true));
+				ok_new_ctr += 1;
+			} else if let (Some((commit_id, true)), true) = (earlier_verdict, auto_accept) {
+				say!("Accepting string '{}' because it was restored to the translation approved in commit {} (ID {}).",
+					po::visible(&qu.orig), commit_id, subj_id);
+				e.insert(toml::Value::Boolean(true));
+				ok_new_ctr += 1;
+				restored_ctr += 1;
+			} else if auto_accept && is_obviously_equal(&qu) {
+				say!("Fast-forwarding string '{}' because equal according to translator (ID {}).", po::visible(&qu.orig), subj_id);
+				e.insert(toml::Value::Boolean(true));
+				ok_new_ctr += 1;
+			} else if auto_accept && settings.fuzzy_policy == FuzzyPolicy::Accept && qu.entry.has_flag("fuzzy") {
+				say!("Accepting fuzzy string '{}' as configured (ID {}).", po::visible(&qu.orig), subj_id);
 				e.insert(toml::Value::Boolean(true));
 				ok_new_ctr += 1;
 			} else {
-				match askq(&qu) {
+				match ask_if_interactive(&qu, &history, &known_answers, settings) {
 					PDDesc::Ok => {
 						e.insert(toml::Value::Boolean(true));
 						ok_new_ctr += 1;
@@ -223,14 +1048,14 @@ fn conduct_asking(qsl: Vec<QuestionSubject>, answ: &mut toml::Table, reask_non_o
 				}
 			},
 			Entry::Occupied(mut e) => {
-				println!("Already reviewed string '{}' (ID {}).", qu.orig, subj_id);
+				say!("Already reviewed string '{}' (ID {}).", po::visible(&qu.orig), subj_id);
 				// already contained in ans!
 				let val: &toml::Value = &e.get().clone();
 				if match val.as_bool() {Some(w) => w, None => false} {
 					ok_old_ctr += 1;
 				} else {
 					if reask_non_ok {
-						match askq(&qu) {
+						match ask_if_interactive(&qu, &history, &known_answers, settings) {
 							PDDesc::Ok => {
 								e.insert(toml::Value::Boolean(true));
 								ok_new_ctr += 1;
@@ -252,93 +1077,299 @@ fn conduct_asking(qsl: Vec<QuestionSubject>, answ: &mut toml::Table, reask_non_o
 		}
 	}
 	if notok_new_ctr + notok_old_ctr + ignored_ctr == 0 {
-		println!("Review succeeded ({} times ok, of which {} new and {} loaded from file)",
+		say!("Review succeeded ({} times ok, of which {} new and {} loaded from file)",
 			ok_new_ctr + ok_old_ctr, ok_new_ctr, ok_old_ctr);
 	} else {
-		println!("Review not succeeded ({} times not ok ({} new), {} times ok ({} new), {} ignores)",
+		say!("Review not succeeded ({} times not ok ({} new), {} times ok ({} new), {} ignores)",
 			notok_new_ctr + notok_old_ctr, notok_new_ctr,
 			ok_new_ctr + ok_old_ctr, ok_new_ctr,
 			ignored_ctr);
 	}
+	if obsoleted_ctr + unobsoleted_ctr > 0 {
+		say!("Among them {} entries that became obsolete and {} restored obsolete entries",
+			obsoleted_ctr, unobsoleted_ctr);
+	}
+	if restored_ctr + reintroduced_ctr > 0 {
+		say!("{} translations were restored to approved ones, {} re-introduced rejected ones",
+			restored_ctr, reintroduced_ctr);
+	}
 }
 
 // Git stuff
 
 /// main parser handler and entry function
-fn get_subjects_for_commit(commit_id: &str, repo: &Repository, trans: &Translator, filename_regex :Option<&str>) -> Result<Vec<QuestionSubject>, Error> {
+fn get_subjects_for_commit(commit_id: &str, repo: &Repository, trans: &Translator, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
 	let commit = try!(repo.find_commit(try!(Oid::from_str(commit_id))));
-	let diff = try!(get_diff_for_commit(repo, commit_id));
-	let old_tree = try!(try!(commit.parent(0)).tree());
 	let new_tree = try!(commit.tree());
-
-	return get_subjects_from_diff_and_trees(&diff, repo, old_tree, new_tree, trans, commit_id, filename_regex);
-}
-
-fn selfcontained_blob_parser(rep: &Repository, tree: &Tree, fname: &str, opt_btm: Option<&BTreeMap<String, String>>) -> Result<BTreeMap<String, String>, Error> {
-	let obj = try!(get_obj_for_filename_and_tree(rep, tree, fname));
-	let blob_cont = otry!(obj.as_blob()).content();
-	return blob_parser(otry!(str::from_utf8(blob_cont).ok()), opt_btm);
-}
-
-fn blob_parser(blob_cont: &str, opt_btm: Option<&BTreeMap<String, String>>) -> Result<BTreeMap<String, String>, Error> {
-	let mut res = BTreeMap::new();
-	let mut msgid: Option<String> = None;
-	let mut msgstr: Option<String> = None;
-	let mut multi_line_mode = 0; // 0 = off, 1 = for msgid, 2 = for msgstr
-	macro_rules! handle_pair {
-		() => { {
-			if msgid.is_some() && msgstr.is_some() {
-				let mut msg_raw_id = msgid.take().unwrap();
-				let mut msg_raw_str = msgstr.take().unwrap();
-				msg_raw_id = msg_raw_id.replace("\\n", " | ");
-				msg_raw_str = msg_raw_str.replace("\\n", " | ");
-				if match opt_btm {
-					Some(opt_btm_tr) => match opt_btm_tr.get(&msg_raw_id) {
-						Some(old_msg_raw_str) => (&msg_raw_str != old_msg_raw_str), // record changed entries
-						None => true, // record new entries
-					},
-					None => true, // record everything for the first run
-				} {
-					// Allow everything except where msgid is "". This is special.
-					if msg_raw_id != "" {
-						res.insert(String::from(msg_raw_id), msg_raw_str);
-					}
-				}
+	let parent_ids = commit.parent_ids().collect::<Vec<Oid>>();
+	let missing_parent = parent_ids.iter().find(|id| repo.find_commit(**id).is_err()).cloned();
+	if parent_ids.is_empty() || missing_parent.is_some() {
+		match missing_parent {
+			Some(id) => say!("Parent {} of commit {} is not in the repo, is it a shallow clone?", id, commit_id),
+			None => say!("Commit {} has no parent", commit_id),
+		}
+		let old_tree = match settings.base {
+			Some(base) => {
+				say!("Reviewing against the given base {}", base);
+				Some(try!(find_tree_for_base(repo, base)))
+			},
+			None => {
+				say!("Reviewing all files as added. Use --base=<commit> to review against another commit.");
+				None
+			},
+		};
+		return get_subjects_against_tree(repo, old_tree, new_tree, trans, commit_id, settings);
+	}
+	if parent_ids.len() < 2 || settings.merge_mode == MergeMode::FirstParent {
+		let old_tree = try!(try!(commit.parent(0)).tree());
+		return get_subjects_against_tree(repo, Some(old_tree), new_tree, trans, commit_id, settings);
+	}
+	match settings.merge_mode {
+		MergeMode::Base => {
+			let mut base = parent_ids[0];
+			for parent_id in parent_ids[1 ..].iter() {
+				base = try!(repo.merge_base(base, *parent_id));
 			}
-		} }
-	}
-	for line in blob_cont.lines() {
-		if line.starts_with("\"") && (multi_line_mode > 0) {
-			match multi_line_mode {
-				1 => {
-					let s = msgid.unwrap();
-					msgid = Some(String::from(s) + line.trim_matches('"'));
-				},
-				2 => {
-					let s = msgstr.unwrap();
-					msgstr = Some(String::from(s) + line.trim_matches('"'));
-				},
-				_ => {
-					panic!("This shouldn't happen!");
-				},
+			say!("Merge commit, reviewing against merge base {}", base);
+			let old_tree = try!(try!(repo.find_commit(base)).tree());
+			return get_subjects_against_tree(repo, Some(old_tree), new_tree, trans, commit_id, settings);
+		},
+		_ => {
+			say!("Merge commit, only reviewing changes not present in any parent");
+			let old_tree = try!(try!(commit.parent(0)).tree());
+			// Most subjects get dropped, so only translate the remaining ones
+			// instead of spending translation quota on all of them.
+			let mut res = try!(get_subjects_against_tree(repo, Some(old_tree), try!(commit.tree()),
+				&NoTranslator, commit_id, settings));
+			for i in 1 .. parent_ids.len() {
+				let other_tree = try!(try!(commit.parent(i)).tree());
+				let other = try!(get_subjects_against_tree(repo, Some(other_tree), try!(commit.tree()),
+					&NoTranslator, commit_id, settings));
+				retain_changed_against(&mut res, &other);
 			}
-		} else {
-			multi_line_mode = 0;
-			handle_pair!();
-		}
-		if line.starts_with("msg") {
-
-			if line.starts_with("msgid ") {
-				msgid = Some(String::from(line["msgid ".len() .. ].trim_matches('"')));
-				multi_line_mode = 1;
-			} else if line.starts_with("msgstr ") {
-				msgstr = Some(String::from(line["msgstr ".len() .. ].trim_matches('"')));
-				multi_line_mode = 2;
+			for qs in res.iter_mut() {
+				qs.translate(trans);
 			}
+			return Ok(res);
+		},
+	}
+}
+
+/// Keeps the subjects that are changes against another parent of a merge as well.
+/// Others were taken over from that parent unchanged.
+fn retain_changed_against(res: &mut Vec<QuestionSubject>, other_parent: &[QuestionSubject]) {
+	let other_ids = other_parent.iter().map(|qs| qs.get_subject_id()).collect::<Vec<_>>();
+	res.retain(|qs| other_ids.contains(&qs.get_subject_id()));
+}
+
+/// Resolves any revision expression, like "HEAD~3", a branch name,
+/// a tag or a short hash, to the full id of the commit it names
+fn resolve_commit(repo: &Repository, rev: &str) -> Result<String, Error> {
+	let obj = try!(repo.revparse_single(rev)
+		.map_err(|e| Error::from_str(&format!("Could not resolve '{}': {}", rev, e))));
+	let commit = try!(obj.peel(ObjectType::Commit)
+		.map_err(|e| Error::from_str(&format!("'{}' does not name a commit: {}", rev, e))));
+	let id = format!("{}", commit.id());
+	if id != rev {
+		say!("Resolved {} to {}", rev, id);
+	}
+	return Ok(id);
+}
+
+/// Finds the tree for the --base option, which may name a commit or a tree
+fn find_tree_for_base<'repo>(repo: &'repo Repository, base: &str) -> Result<Tree<'repo>, Error> {
+	let obj = try!(repo.revparse_single(base)
+		.map_err(|e| Error::from_str(&format!("Could not resolve base '{}': {}", base, e))));
+	let tree_obj = try!(obj.peel(ObjectType::Tree)
+		.map_err(|e| Error::from_str(&format!("Base {} is neither a commit nor a tree: {}", base, e))));
+	return repo.find_tree(tree_obj.id());
+}
+
+/// Creates subjects for the changes from old_tree to new_tree.
+/// Without old_tree, all files in new_tree count as added.
+fn get_subjects_against_tree(repo: &Repository, old_tree: Option<Tree>, new_tree: Tree, trans: &Translator, commit_id: &str, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
+	let mut options = DiffOptions::new();
+	options.include_typechange(true);
+	let mut diff = try!(Diff::tree_to_tree(repo, old_tree.as_ref(), Some(&new_tree), Some(&mut options)));
+	try!(diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true))));
+	for st in try!(get_changed_filenames(&diff)) {
+		say!("Seen file change: {}; ", st);
+	}
+	return get_subjects_from_diff_and_trees(&diff, repo, old_tree.map(Snapshot::Tree), Snapshot::Tree(new_tree),
+		trans, commit_id, settings);
+}
+
+/// Creates subjects for the staged or unstaged changes in the working copy.
+/// As there is no commit yet, answers are recorded for the content.
+fn get_subjects_for_uncommitted(mode: UncommittedMode, repo: &Repository, trans: &Translator, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
+	let mut options = DiffOptions::new();
+	options.include_typechange(true);
+	let index = try!(repo.index());
+	let (mut diff, old, new) = match mode {
+		UncommittedMode::Staged => {
+			say!("Checking changes staged for commit");
+			let head_tree = match repo.revparse_single("HEAD") {
+				Ok(obj) => Some(try!(repo.find_tree(try!(obj.peel(ObjectType::Tree)).id()))),
+				Err(_) => {
+					say!("HEAD does not point to a commit yet, reviewing all files as added");
+					None
+				},
+			};
+			let diff = try!(repo.diff_tree_to_index(head_tree.as_ref(), Some(&index), Some(&mut options)));
+			(diff, head_tree.map(Snapshot::Tree), Snapshot::Index(index))
+		},
+		UncommittedMode::Unstaged => {
+			say!("Checking changes not staged for commit");
+			if repo.workdir().is_none() {
+				return Err(Error::from_str("Repository has no working directory"));
+			}
+			options.include_untracked(true).recurse_untracked_dirs(true);
+			let diff = try!(repo.diff_index_to_workdir(Some(&index), Some(&mut options)));
+			(diff, Some(Snapshot::Index(index)), Snapshot::Workdir)
+		},
+	};
+	try!(diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true))));
+	for st in try!(get_changed_filenames(&diff)) {
+		say!("Seen file change: {}; ", st);
+	}
+	return get_subjects_from_diff_and_trees(&diff, repo, old, new, trans, UNCOMMITTED_ID, settings);
+}
+
+/// A state of the repo files can be read from
+enum Snapshot<'repo> {
+	Tree(Tree<'repo>),
+	Index(Index),
+	Workdir,
+}
+
+impl<'repo> Snapshot<'repo> {
+	fn read_file(&self, repo: &Repository, fname: &str) -> Result<Vec<u8>, Error> {
+		match *self {
+			Snapshot::Tree(ref tree) => {
+				let obj = try!(get_obj_for_filename_and_tree(repo, tree, fname));
+				let blob = try!(obj.as_blob().ok_or(Error::from_str(&format!("{} is no file", fname))));
+				return Ok(blob.content().to_vec());
+			},
+			Snapshot::Index(ref index) => {
+				let entry = try!(index.get_path(Path::new(fname), 0).ok_or(
+					Error::from_str(&format!("file {} not found in the index", fname))));
+				return Ok(try!(repo.find_blob(entry.id)).content().to_vec());
+			},
+			Snapshot::Workdir => {
+				let workdir = try!(repo.workdir().ok_or(Error::from_str("Repository has no working directory")));
+				return read_workdir_file(workdir, fname);
+			},
+		}
+	}
+}
+
+/// Reads a file of the working directory, refusing to read anything outside of it
+fn read_workdir_file(workdir: &Path, fname: &str) -> Result<Vec<u8>, Error> {
+	let outside = || Error::from_str(&format!("{} is outside of the repository", fname));
+	let rel_path = try!(repo_relative_path(Path::new(fname)).ok_or_else(&outside));
+	let read_err = |e: io::Error| Error::from_str(&format!("Could not read {}: {}", fname, e));
+	// symlinks may lead outside as well
+	let path = try!(fs::canonicalize(workdir.join(rel_path)).map_err(&read_err));
+	if !path.starts_with(try!(fs::canonicalize(workdir).map_err(&read_err))) {
+		return Err(outside());
+	}
+	let mut cont = Vec::new();
+	try!(File::open(path).and_then(|mut f| f.read_to_end(&mut cont)).map_err(&read_err));
+	return Ok(cont);
+}
+
+fn selfcontained_blob_parser(rep: &Repository, snapshot: &Snapshot, fname: &str) -> Result<PoFile, Error> {
+	let blob_cont = try!(snapshot.read_file(rep, fname));
+	return blob_parser(&blob_cont, fname);
+}
+
+/// Finds the charset declared in the Content-Type field of the header entry.
+/// Only the header entry (up to the first blank line) is looked at, as it is ASCII,
+/// so this works before knowing the charset, and regardless of problems in other entries.
+fn header_charset(blob_cont: &[u8]) -> Option<String> {
+	let cont = String::from_utf8_lossy(blob_cont);
+	for line in cont.lines().skip_while(|l| l.trim().is_empty()).take_while(|l| !l.trim().is_empty()) {
+		let pos = match line.find("Content-Type:") {
+			Some(pos) => pos,
+			None => continue,
 		};
+		let field = &line[pos ..];
+		return field.find("charset=").map(|cs_pos| field[cs_pos + "charset=".len() ..]
+			.chars()
+			.take_while(|c| !c.is_whitespace() && *c != '\\' && *c != '"' && *c != ';')
+			.collect());
 	}
-	handle_pair!();
-	return Ok(res);
+	return None;
+}
+
+/// Returns the encoding declared in the header, or None for UTF-8
+fn blob_encoding(blob_cont: &[u8], fname: &str) -> Result<Option<EncodingRef>, Error> {
+	let charset = header_charset(blob_cont);
+	let charset = match charset {
+		Some(ref cs) if !cs.is_empty() && cs.to_lowercase() != "utf-8" && cs.to_lowercase() != "utf8" && cs != "CHARSET" => cs,
+		_ => return Ok(None),
+	};
+	return match encoding_from_whatwg_label(charset) {
+		Some(enc) => Ok(Some(enc)),
+		None => Err(Error::from_str(&format!("{} declares unknown charset '{}'", fname, charset))),
+	};
+}
+
+fn decode_bytes(bytes: &[u8], enc: Option<EncodingRef>) -> Result<String, String> {
+	return match enc {
+		Some(enc) => enc.decode(bytes, DecoderTrap::Strict).map_err(|e| format!("not valid {}: {}", enc.name(), e)),
+		None => String::from_utf8(bytes.to_vec()).map_err(|e| format!("not valid UTF-8: {}", e)),
+	};
+}
+
+/// Decodes and parses the po file content, using the charset declared in its header.
+/// The bytes given by escape sequences are in that charset as well.
+fn blob_parser(blob_cont: &[u8], fname: &str) -> Result<PoFile, Error> {
+	let enc = try!(blob_encoding(blob_cont, fname));
+	let cont = try!(decode_bytes(blob_cont, enc)
+		.map_err(|e| Error::from_str(&format!("{} is {}", fname, e))));
+	return po::parse_with_decoder(&cont, &|bytes| decode_bytes(bytes, enc))
+		.map_err(|e| Error::from_str(&format!("Could not parse {}: {}", fname, e)));
+}
+
+/// Returns the entries of new_po that were added or changed relative to old_po,
+/// together with their old version (same msgctxt and msgid) if there is one.
+/// Entries that became obsolete or were restored from obsolete ones are included too.
+/// The header entry (msgid "") is special and not included.
+fn changed_entries<'a>(old_po: Option<&'a PoFile>, new_po: &'a PoFile) -> Vec<(SubjectKind, Option<&'a PoEntry>, &'a PoEntry)> {
+	let mut res = Vec::new();
+	for entry in new_po.entries.iter() {
+		if entry.is_header() {
+			continue;
+		}
+		let msgctxt = entry.msgctxt.as_ref().map(|c| c.as_ref());
+		let old_entry = old_po.and_then(|p| p.find(msgctxt, &entry.msgid));
+		if entry.obsolete {
+			// record entries that became obsolete
+			if old_entry.is_some() {
+				res.push((SubjectKind::Obsoleted, old_entry, entry));
+			}
+			continue;
+		}
+		match old_entry {
+			Some(old_entry) => if old_entry.msgstr != entry.msgstr // record changed entries
+					|| old_entry.has_flag("fuzzy") != entry.has_flag("fuzzy") { // and fuzzy toggles
+				res.push((SubjectKind::Translation, Some(old_entry), entry));
+			},
+			None => match old_po.and_then(|p| p.find_obsolete(msgctxt, &entry.msgid)) {
+				Some(obsolete_entry) => res.push((SubjectKind::Unobsoleted, Some(obsolete_entry), entry)),
+				None => {
+					// if the source text changed, compare with the entry for the old source text
+					let previous_entry = match (old_po, entry.previous_msgid.as_ref()) {
+						(Some(p), Some(prev)) => p.find(entry.previous_msgctxt.as_ref().map(|c| c.as_ref()), prev),
+						_ => None,
+					};
+					res.push((SubjectKind::Translation, previous_entry, entry)); // record new entries
+				},
+			},
+		}
+	}
+	return res;
 }
 
 fn get_obj_for_filename_and_tree<'repo>(rep: &'repo Repository, tree: &Tree, fname: &str) -> Result<Object<'repo>, Error> {
@@ -348,70 +1379,424 @@ fn get_obj_for_filename_and_tree<'repo>(rep: &'repo Repository, tree: &Tree, fna
 	return Ok(tree_obj);
 }
 
+// How many lines to show before and after a referenced source line
+const SNIPPET_CONTEXT_LINES: usize = 2;
+// How many references of an entry to show source lines for
+const MAX_SNIPPETS: usize = 2;
+
+/// Normalizes a path relative to the repo root, returning None
+/// for absolute paths and paths that lead outside of the repo
+fn repo_relative_path(path: &Path) -> Option<String> {
+	let mut parts: Vec<String> = Vec::new();
+	for comp in path.components() {
+		match comp {
+			std::path::Component::ParentDir => if parts.pop().is_none() {
+				return None;
+			},
+			std::path::Component::Normal(p) => parts.push(p.to_string_lossy().into_owned()),
+			std::path::Component::CurDir => (),
+			_ => return None,
+		}
+	}
+	if parts.is_empty() {
+		return None;
+	}
+	return Some(parts.join("/"));
+}
+
+/// Returns the paths in the repo a source reference path may point to.
+/// References are usually relative to the repo root or to the po file's directory.
+/// The paths come from the reviewed file, so any outside of the repo are left out.
+fn reference_path_candidates(po_fname: &str, path: &str) -> Vec<String> {
+	let po_dir = Path::new(po_fname).parent().unwrap_or(Path::new(""));
+	let mut res = Vec::new();
+	for candidate in vec![repo_relative_path(Path::new(path)), repo_relative_path(&po_dir.join(path))] {
+		match candidate {
+			Some(candidate) => if !res.contains(&candidate) {
+				res.push(candidate);
+			},
+			None => (),
+		}
+	}
+	return res;
+}
+
+/// Reads the lines around a source reference like "src/main.c:42" from the snapshot
+fn source_snippet(repo: &Repository, snapshot: &Snapshot, po_fname: &str, reference: &str) -> Option<String> {
+	let mut parts = reference.rsplitn(2, ':');
+	let line_no = match parts.next().and_then(|l| l.parse::<usize>().ok()) {
+		Some(line_no) if line_no > 0 => line_no,
+		_ => return None,
+	};
+	let path = match parts.next() {
+		Some(path) => path,
+		None => return None,
+	};
+	for candidate in reference_path_candidates(po_fname, path) {
+		let blob_cont = match snapshot.read_file(repo, &candidate) {
+			Ok(blob_cont) => blob_cont,
+			Err(_) => continue,
+		};
+		let cont = String::from_utf8_lossy(&blob_cont);
+		let first = if line_no > SNIPPET_CONTEXT_LINES { line_no - SNIPPET_CONTEXT_LINES } else { 1 };
+		let mut res = format!("{}:", candidate);
+		for (i, line) in cont.lines().enumerate().skip(first - 1).take(line_no + SNIPPET_CONTEXT_LINES + 1 - first) {
+			let marker = if i + 1 == line_no { ">" } else { " " };
+			res.push_str(&format!("\n{}{:>5} | {}", marker, i + 1, line));
+		}
+		return Some(res);
+	}
+	return None;
+}
+
+fn source_snippets(repo: &Repository, snapshot: &Snapshot, po_fname: &str, entry: &PoEntry) -> Vec<String> {
+	return entry.references.iter()
+		.filter_map(|r| source_snippet(repo, snapshot, po_fname, r))
+		.take(MAX_SNIPPETS)
+		.collect();
+}
+
 fn filename_to_language<'a>(filename :&'a str, regex :&str) -> Option<&'a str> {
 	let re = Regex::new(regex).unwrap();;
 	return re.captures(filename).map_or(None, |cap| cap.at(1));
 }
 
-fn get_subjects_from_diff_and_trees(diff: &Diff, repo: &Repository, tree_old: Tree, tree_new: Tree, trans: &Translator, commit_id: &str, filename_regex :Option<&str>) -> Result<Vec<QuestionSubject>, Error> {
+// Header fields that change with nearly every update, and don't need review
+const HEADER_NOISE_FIELDS: &'static [&'static str] = &["PO-Revision-Date", "POT-Creation-Date", "X-Generator"];
+
+/// Lists the header fields that were added, removed or changed
+fn header_diff(old: Option<&PoEntry>, new: &PoEntry) -> Vec<String> {
+	let old_fields = old.map_or(Vec::new(), |h| h.header_fields());
+	let new_fields = new.header_fields();
+	let mut res = Vec::new();
+	let mut unchanged = 0;
+	for &(ref key, ref val) in old_fields.iter() {
+		match new_fields.iter().find(|&&(ref k, _)| k == key) {
+			Some(&(_, ref new_val)) if new_val == val => unchanged += 1,
+			Some(&(_, ref new_val)) => res.push(format!("  {}: '{}' -> '{}'", key, val, new_val)),
+			None => res.push(format!("- {}: '{}'", key, val)),
+		}
+	}
+	for &(ref key, ref val) in new_fields.iter() {
+		if !old_fields.iter().any(|&(ref k, _)| k == key) {
+			res.push(format!("+ {}: '{}'", key, val));
+		}
+	}
+	if unchanged > 0 {
+		res.push(format!("  ({} unchanged fields)", unchanged));
+	}
+	return res;
+}
+
+fn normalize_lang(lang: &str) -> String {
+	return lang.to_lowercase().replace("-", "_");
+}
+
+/// Sanity checks for the header of a po file
+fn header_warnings(po: &PoFile, from_lang: Option<&str>) -> Vec<String> {
+	let mut res = Vec::new();
+	match (po.header_field("Language"), from_lang) {
+		(Some(ref lang), Some(from_lang)) if normalize_lang(lang) != normalize_lang(from_lang) =>
+			res.push(format!("Language header '{}' doesn't match language '{}' from the filename", lang, from_lang)),
+		(None, _) => res.push("no Language header".to_string()),
+		_ => (),
+	}
+	match po.charset() {
+		Some(ref cs) if cs == "CHARSET" => res.push("charset is still the template placeholder 'CHARSET'".to_string()),
+		Some(_) => (),
+		None => res.push("no charset declared in Content-Type header".to_string()),
+	}
+	match po.plural_forms() {
+		Some(Ok(pf)) => {
+			if let Some(n) = (0 .. 1000).find(|n| pf.index_for(*n).map_or(true, |i| i >= pf.nplurals)) {
+				res.push(format!("plural formula gives no valid form for n = {} with nplurals={}", n, pf.nplurals));
+			}
+		},
+		Some(Err(e)) => res.push(format!("invalid Plural-Forms header: {}", e)),
+		None => (),
+	}
+	return res;
+}
+
+/// Creates a question subject for the header, if it changed in a relevant way
+fn header_subject(commit_id: &str, fname: &str, old_po: Option<&PoFile>, new_po: &PoFile,
+		from_lang: Option<&str>) -> Option<QuestionSubject> {
+	let old_header = old_po.and_then(|p| p.header());
+	let new_header = new_po.header();
+	let relevant_fields = |h: Option<&PoEntry>| h.map_or(Vec::new(), |h| h.header_fields().into_iter()
+		.filter(|&(ref k, _)| !HEADER_NOISE_FIELDS.contains(&k.as_ref()))
+		.collect::<Vec<_>>());
+	if old_po.is_some() && relevant_fields(old_header) == relevant_fields(new_header) {
+		return None;
+	}
+	let mut warnings = header_warnings(new_po, from_lang);
+	if let Some(old_po) = old_po {
+		let (old_cs, new_cs) = (old_po.charset(), new_po.charset());
+		if old_cs.as_ref().map(|c| c.to_lowercase()) != new_cs.as_ref().map(|c| c.to_lowercase()) {
+			warnings.push(format!("charset changed from {} to {}",
+				old_cs.unwrap_or("<none>".to_string()), new_cs.unwrap_or("<none>".to_string())));
+		}
+	}
+	match (old_header, new_header) {
+		(Some(_), None) => warnings.push("the header was removed".to_string()),
+		(None, None) => warnings.push("the file has no header".to_string()),
+		_ => (),
+	}
+	return Some(QuestionSubject {
+		kind: SubjectKind::Header,
+		commit_id: commit_id.to_string(),
+		from_filename: fname.to_string(),
+		old: old_header.map(|h| h.msgstr[0].clone()),
+		new: new_header.map_or(String::new(), |h| h.msgstr[0].clone()),
+		entry: new_header.cloned().unwrap_or_default(),
+		old_entry: old_header.cloned(),
+		warnings: warnings,
+		.. QuestionSubject::default()
+	});
+}
+
+/// Parses the Plural-Forms header of the file and warns about
+/// plural entries whose number of forms doesn't match it
+fn check_plural_forms(fname: &str, po: &PoFile) -> Option<PluralForms> {
+	let plural_entries = po.entries.iter().filter(|e| !e.obsolete && e.msgid_plural.is_some());
+	let pf = match po.plural_forms() {
+		Some(Ok(pf)) => pf,
+		Some(Err(e)) => {
+			say!("Warning: invalid Plural-Forms header in {}: {}", fname, e);
+			return None;
+		},
+		None => {
+			if plural_entries.count() > 0 {
+				say!("Warning: {} has plural entries but no Plural-Forms header", fname);
+			}
+			return None;
+		},
+	};
+	let mismatches = plural_entries.filter(|e| e.msgstr.len() != pf.nplurals).count();
+	if mismatches > 0 {
+		say!("Warning: {} plural entries in {} don't have the nplurals={} forms the header declares",
+			mismatches, fname, pf.nplurals);
+	}
+	return Some(pf);
+}
+
+/// Creates the subject for an entry that became obsolete
+fn obsoleted_subject(commit_id: &str, fname: &str, old_entry: &PoEntry, entry: &PoEntry,
+		from_lang: Option<&str>) -> QuestionSubject {
+	return QuestionSubject {
+		kind: SubjectKind::Obsoleted,
+		commit_id: commit_id.to_string(),
+		from_filename: fname.to_string(),
+		orig: entry.msgid.clone(),
+		old: Some(old_entry.msgstr[0].clone()),
+		entry: entry.clone(),
+		old_entry: Some(old_entry.clone()),
+		from_lang: from_lang.map(|l| l.to_string()),
+		.. QuestionSubject::default()
+	};
+}
+
+/// Creates the question subjects for a changed entry, one for each changed plural form
+fn subjects_for_entry(kind: SubjectKind, commit_id: &str, fname: &str, old_file_exists: bool, old_entry: Option<&PoEntry>, entry: &PoEntry,
+		plural_forms: Option<&PluralForms>, from_lang: Option<&str>) -> Vec<QuestionSubject> {
 	let mut res = Vec::new();
+	let mut warnings = Vec::new();
+	if let (Some(pf), true) = (plural_forms, entry.msgid_plural.is_some()) {
+		if entry.msgstr.len() != pf.nplurals {
+			warnings.push(format!("entry has {} plural forms, but the header says nplurals={}",
+				entry.msgstr.len(), pf.nplurals));
+		}
+	}
+	let format_kinds = format::format_kinds(entry);
+	let fuzzy_toggled = old_entry.map_or(false, |e| e.has_flag("fuzzy") != entry.has_flag("fuzzy"));
+	// The source text before it changed, either from "#|" comments,
+	// or from the old entry if it had a different msgid
+	let (prev_msgid, prev_msgid_plural) = if entry.previous_msgid.is_some() {
+		(entry.previous_msgid.as_ref(), entry.previous_msgid_plural.as_ref())
+	} else {
+		match old_entry {
+			Some(e) if e.msgid != entry.msgid => (Some(&e.msgid), e.msgid_plural.as_ref()),
+			Some(e) => (e.previous_msgid.as_ref(), e.previous_msgid_plural.as_ref()),
+			None => (None, None),
+		}
+	};
+	let source_changed = old_entry.map_or(false, |e| e.msgid != entry.msgid);
+	// Obsolete entries were never reviewed, so ask about all forms of restored ones
+	let ask_all_forms = fuzzy_toggled || source_changed || kind == SubjectKind::Unobsoleted;
+	for (i, val) in entry.msgstr.iter().enumerate() {
+		let oldval = old_entry.and_then(|e| e.msgstr.get(i));
+		if oldval == Some(val) && !ask_all_forms {
+			continue;
+		}
+		let (orig, plural_index, plural_rule) = match entry.msgid_plural {
+			Some(ref msgid_plural) => (if i == 0 { &entry.msgid } else { msgid_plural },
+				Some(i), Some(match plural_forms {
+					Some(pf) => pf.describe(i),
+					None => format!("form {}", i),
+				})),
+			None => (&entry.msgid, None, None),
+		};
+		let format_problems = format_kinds.iter()
+			.flat_map(|kind| match entry.msgid_plural {
+				Some(ref msgid_plural) => {
+					// without a header, assume form 0 is only used for n = 1
+					let single_number = plural_forms.map_or(i == 0, |pf| pf.examples(i, 2).len() == 1);
+					format::check_plural(*kind, msgid_plural, val, single_number)
+				},
+				None => format::check(*kind, orig, val),
+			}.into_iter())
+			.collect();
+		let previous_orig = match if plural_index.unwrap_or(0) == 0 { prev_msgid } else { prev_msgid_plural } {
+			Some(prev) if prev != orig => Some(prev.clone()),
+			_ => None,
+		};
+		let mut form_warnings = warnings.clone();
+		if let Some(ref prev) = previous_orig {
+			if worddiff::is_meaningful_change(prev, orig) && oldval == Some(val) {
+				form_warnings.push("the source text changed, but the translation was left unchanged".to_string());
+			}
+		}
+		res.push(QuestionSubject {
+			kind: kind,
+			commit_id: commit_id.to_string(),
+			from_filename: fname.to_string(),
+			orig: orig.clone(),
+			previous_orig: previous_orig,
+			old: oldval.cloned(),
+			new: val.clone(),
+			// translations are filled in later
+			oldtrans: match oldval {
+				Some(_) => String::new(),
+				None if old_file_exists => "?????".to_string(),
+				None => "<no old version available>".to_string()},
+			newtrans: String::new(),
+			entry: entry.clone(),
+			old_entry: old_entry.cloned(),
+			plural_index: plural_index,
+			plural_rule: plural_rule,
+			warnings: form_warnings,
+			format_problems: format_problems,
+			source_snippets: Vec::new(),
+			template_entry: None,
+			author: None,
+			author_email: None,
+			committer: None,
+			committer_email: None,
+			from_lang: from_lang.map(|l| l.to_string()),
+		});
+	}
+	return res;
+}
+
+fn get_subjects_from_diff_and_trees(diff: &Diff, repo: &Repository, tree_old: Option<Snapshot>, tree_new: Snapshot, trans: &Translator, commit_id: &str, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
+	let mut res = Vec::new();
+	let filename_regex = settings.filename_regex;
+	let template = match settings.template {
+		Some(path) => match selfcontained_blob_parser(repo, &tree_new, path) {
+			Ok(template) => Some(template),
+			Err(e) => {
+				say!("Warning: could not load template {}, not checking for orphans: {}", path, e);
+				None
+			},
+		},
+		None => None,
+	};
 	let changed_filenames = try!(get_changed_filenames(diff));
 	for fname in changed_filenames {
 		if filename_regex.is_none() && !fname.filename.ends_with(".po") {
-			println!("Ignoring non-po ending file {}", fname.filename);
+			say!("Ignoring non-po ending file {}", fname.filename);
 			continue;
 		}
 		let from_lang = filename_regex.map_or(None, |regex| filename_to_language(&fname.filename, regex));
 		if from_lang.is_some() {
-			println!("Detected language '{}' for file {}", from_lang.unwrap(), fname.filename);
+			say!("Detected language '{}' for file {}", from_lang.unwrap(), fname.filename);
 		}
 		if filename_regex.is_some() && from_lang.is_none() {
-			println!("Ignoring file not matching given regex {}", fname.filename);
+			say!("Ignoring file not matching given regex {}", fname.filename);
 			continue;
 		}
-		match fname.reason {
+		let fnamef: &str = fname.filename.as_ref();
+		let (old_po, new_po) = match fname.reason {
 			FilenameChangeReason::Add => {
-				let fnamef = fname.filename.as_ref();
-				let po_map = try!(selfcontained_blob_parser(repo, &tree_new, fnamef, None));
 				// we have no old versions
-				for (key, val) in po_map.iter() {
-					res.push(QuestionSubject {
-						commit_id: commit_id.to_string(),
-						from_filename: fnamef.to_string(),
-						orig: key.to_string(),
-						old: None,
-						new: val.to_string(),
-						oldtrans: "<no old version available>".to_string(),
-						newtrans: trans.translate(val, from_lang),
-					});
-				}
+				(None, try!(selfcontained_blob_parser(repo, &tree_new, fnamef)))
 			},
 			FilenameChangeReason::Modify => {
-				let fnamef = fname.filename.as_ref();
-				let old_po_map = try!(selfcontained_blob_parser(repo, &tree_old, fnamef, None));
-				let new_po_map = try!(selfcontained_blob_parser(repo, &tree_new, fnamef, Some(&old_po_map)));
 				// we have old and new versions
-				// the new po map is filled with the actually differing mentions
-				for (key, val) in new_po_map.iter() {
-					let oldval = old_po_map.get(key);
-					res.push(QuestionSubject {
-						commit_id: commit_id.to_string(),
-						from_filename: fnamef.to_string(),
-						orig: key.to_string(),
-						old: match oldval { Some(v) => Some(v.clone()), None => None},
-						new: val.to_string(),
-						oldtrans: match oldval {
-							Some(v) => trans.translate(v, from_lang),
-							None => "?????".to_string()},
-						newtrans: trans.translate(val, from_lang),
-					});
+				let tree_old = try!(tree_old.as_ref().ok_or(Error::from_str(
+					&format!("{} is modified, but there is no old version", fnamef))));
+				(Some(try!(selfcontained_blob_parser(repo, tree_old, fnamef))),
+					try!(selfcontained_blob_parser(repo, &tree_new, fnamef)))
+			},
+			FilenameChangeReason::Rename(ref from) | FilenameChangeReason::Copy(ref from) => {
+				// review against the old path
+				let tree_old = try!(tree_old.as_ref().ok_or(Error::from_str(
+					&format!("{} is {}, but there is no old version", fnamef, fname.reason))));
+				let old_lang = filename_regex.map_or(None, |regex| filename_to_language(from, regex));
+				let mut warnings = Vec::new();
+				if old_lang.is_some() && old_lang != from_lang {
+					warnings.push(format!("language changed from '{}' to '{}'",
+						old_lang.unwrap(), from_lang.unwrap_or("<none>")));
 				}
+				res.push(QuestionSubject {
+					kind: match fname.reason {
+						FilenameChangeReason::Rename(_) => SubjectKind::Rename,
+						_ => SubjectKind::Copy,
+					},
+					commit_id: commit_id.to_string(),
+					from_filename: fnamef.to_string(),
+					old: Some(from.clone()),
+					new: fnamef.to_string(),
+					warnings: warnings,
+					.. QuestionSubject::default()
+				});
+				(Some(try!(selfcontained_blob_parser(repo, tree_old, from))),
+					try!(selfcontained_blob_parser(repo, &tree_new, fnamef)))
 			},
 			FilenameChangeReason::Delete => {
 				// do nothing here, perhaps notify...
+				continue;
 			},
+			FilenameChangeReason::TypeChange => {
+				say!("Ignoring type change of {}", fnamef);
+				continue;
+			},
+		};
+		if let Some(qs) = header_subject(commit_id, fnamef, old_po.as_ref(), &new_po, from_lang) {
+			res.push(qs);
+		}
+		let plural_forms = check_plural_forms(fnamef, &new_po);
+		for (kind, old_entry, entry) in changed_entries(old_po.as_ref(), &new_po) {
+			if kind == SubjectKind::Obsoleted {
+				res.push(obsoleted_subject(commit_id, fnamef, old_entry.unwrap(), entry, from_lang));
+				continue;
+			}
+			if settings.fuzzy_policy == FuzzyPolicy::Skip && entry.has_flag("fuzzy") {
+				continue;
+			}
+			let mut subjects = subjects_for_entry(kind, commit_id, fnamef, old_po.is_some(), old_entry, entry,
+				plural_forms.as_ref(), from_lang);
+			if !subjects.is_empty() {
+				let snippets = source_snippets(repo, &tree_new, fnamef, entry);
+				// None if there is no template, Some(None) if the entry is not in it
+				let template_entry = template.as_ref()
+					.map(|t| t.find(entry.msgctxt.as_ref().map(|c| c.as_ref()), &entry.msgid));
+				for qs in subjects.iter_mut() {
+					qs.source_snippets = snippets.clone();
+					match template_entry {
+						Some(Some(te)) => qs.template_entry = Some(te.clone()),
+						Some(None) => qs.warnings.push(format!("orphan entry, its msgid is not in the template {}",
+							settings.template.unwrap())),
+						None => (),
+					}
+				}
+			}
+			res.extend(subjects);
 		}
 	}
+	for qs in res.iter_mut() {
+		qs.translate(trans);
+	}
 	return Ok(res);
 }
 
@@ -420,6 +1805,12 @@ enum FilenameChangeReason {
 	Add,
 	Modify,
 	Delete,
+	/// Renamed from the given path
+	Rename(String),
+	/// Copied from the given path
+	Copy(String),
+	/// E.g. a file was replaced by a symlink
+	TypeChange,
 }
 
 impl fmt::Display for FilenameChangeReason {
@@ -428,6 +1819,9 @@ impl fmt::Display for FilenameChangeReason {
 			FilenameChangeReason::Add => write!(f, "add"),
 			FilenameChangeReason::Modify => write!(f, "modify"),
 			FilenameChangeReason::Delete => write!(f, "delete"),
+			FilenameChangeReason::Rename(ref from) => write!(f, "rename from {}", from),
+			FilenameChangeReason::Copy(ref from) => write!(f, "copy from {}", from),
+			FilenameChangeReason::TypeChange => write!(f, "typechange"),
 		}
 	}
 }
@@ -446,28 +1840,331 @@ impl fmt::Display for FilenameChange {
 
 fn get_changed_filenames(diff: &Diff) -> Result<Vec<FilenameChange>, Error> {
 	let mut res = Vec::new();
-	try!(diff.print(DiffFormat::NameStatus, |_delta, _hunk, line| {
-		let st = String::from(str::from_utf8(line.content()).unwrap());
-		let mut stc = st.chars();
-		let reason = match stc.next().unwrap() {
-			'A' => FilenameChangeReason::Add,
-			'D' => FilenameChangeReason::Delete,
-			'M' => FilenameChangeReason::Modify,
-			_ => panic!("Unknown filename change!"),
+	for delta in diff.deltas() {
+		let old_path = delta.old_file().path().map_or(String::new(), |p| p.to_string_lossy().into_owned());
+		let new_path = delta.new_file().path().map_or(String::new(), |p| p.to_string_lossy().into_owned());
+		let reason = match delta.status() {
+			Delta::Added | Delta::Untracked => FilenameChangeReason::Add,
+			Delta::Deleted => FilenameChangeReason::Delete,
+			Delta::Modified => FilenameChangeReason::Modify,
+			Delta::Renamed => FilenameChangeReason::Rename(old_path.clone()),
+			Delta::Copied => FilenameChangeReason::Copy(old_path.clone()),
+			Delta::Typechange => FilenameChangeReason::TypeChange,
+			status => {
+				say!("Ignoring {} with status {:?}", new_path, status);
+				continue;
+			},
+		};
+		let filename = match reason {
+			FilenameChangeReason::Delete => old_path,
+			_ => new_path,
 		};
-		let col: String = stc.collect();
-		let fname = col.trim_left();
-		res.push(FilenameChange{ reason: reason,
-			filename: String::from(fname.trim_right())});
-		true
-	}));
+		res.push(FilenameChange{ reason: reason, filename: filename });
+	}
 	return Ok(res);
 }
 
-fn get_diff_for_commit(repo: &Repository, commit_id: &str) -> Result<Diff, Error> {
-	let commit = try!(repo.find_commit(try!(Oid::from_str(commit_id))));
-	let mut options = DiffOptions::new();
-	return Diff::tree_to_tree(repo,
-		try!(commit.parent(0)).tree().ok().as_ref(),
-		commit.tree().ok().as_ref(),Some(&mut options));
+/// Splits a range like "A..B" into its ends
+fn split_range(range: &str) -> Option<(&str, &str)> {
+	let pos = match range.find("..") {
+		Some(pos) => pos,
+		None => return None,
+	};
+	return Some((&range[.. pos], &range[pos + 2 ..]));
+}
+
+/// Returns the commits reachable from `to` but not from `from`, oldest first.
+/// Both need to be full commit ids.
+fn get_commits_in_range(repo: &Repository, from: &str, to: &str) -> Result<Vec<String>, Error> {
+	let mut walk = try!(repo.revwalk());
+	walk.set_sorting(SORT_TOPOLOGICAL | SORT_REVERSE);
+	try!(walk.push(try!(Oid::from_str(to))));
+	try!(walk.hide(try!(Oid::from_str(from))));
+	let mut res = Vec::new();
+	for oid in walk {
+		res.push(format!("{}", oid));
+	}
+	return Ok(res);
+}
+
+/// Creates subjects for the net changes between the trees of two commits.
+/// Answers are recorded for the range, not the single commits.
+fn get_subjects_for_range(from: &str, to: &str, repo: &Repository, trans: &Translator, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
+	let old_tree = try!(try!(repo.find_commit(try!(Oid::from_str(from)))).tree());
+	let new_tree = try!(try!(repo.find_commit(try!(Oid::from_str(to)))).tree());
+	let range_id = format!("{}..{}", from, to);
+	return get_subjects_against_tree(repo, Some(old_tree), new_tree, trans, &range_id, settings);
+}
+
+/// Reviews the changes of a branch since it forked off from upstream.
+/// Unless per_commit is set, the changes are reviewed as a whole, like with --net.
+fn get_subjects_for_branch(branch: &str, upstream: &str, per_commit: bool, repo: &Repository, trans: &Translator, settings: &ReviewSettings) -> Result<Vec<QuestionSubject>, Error> {
+	let branch_id = try!(resolve_commit(repo, branch));
+	let upstream_id = try!(resolve_commit(repo, upstream));
+	let base_id = format!("{}", try!(repo.merge_base(try!(Oid::from_str(&branch_id)), try!(Oid::from_str(&upstream_id)))));
+	say!("Branch {} forked off from {} at {}", branch, upstream, base_id);
+	let commits = try!(get_commits_in_range(repo, &base_id, &branch_id));
+	try!(print_branch_summary(repo, &commits, settings));
+	if !per_commit {
+		say!("Checking net changes of branch: {}..{}", base_id, branch_id);
+		return get_subjects_for_range(&base_id, &branch_id, repo, trans, settings);
+	}
+	let mut res = Vec::new();
+	for commit_id in commits {
+		say!("Checking commit identifier: {}", commit_id);
+		res.extend(try!(get_subjects_for_commit(&commit_id, repo, trans, settings)));
+	}
+	return Ok(res);
+}
+
+/// Returns the language of a file that gets reviewed, or its name if no
+/// filename-regex is set. None for files that don't get reviewed.
+fn reviewed_file_language<'a>(fname: &'a str, settings: &ReviewSettings) -> Option<&'a str> {
+	return match settings.filename_regex {
+		Some(regex) => filename_to_language(fname, regex),
+		None if fname.ends_with(".po") => Some(fname),
+		None => None,
+	};
+}
+
+/// Prints which commits touched which languages
+fn print_branch_summary(repo: &Repository, commits: &[String], settings: &ReviewSettings) -> Result<(), Error> {
+	let mut commits_per_lang = std::collections::BTreeMap::new();
+	say!("{} commits in branch:", commits.len());
+	for commit_id in commits {
+		let mut commit = try!(repo.find_commit(try!(Oid::from_str(commit_id))));
+		let old_tree = match commit.parent(0) {
+			Ok(parent) => Some(try!(parent.tree())),
+			Err(_) => None,
+		};
+		let mut diff = try!(Diff::tree_to_tree(repo, old_tree.as_ref(), Some(&try!(commit.tree())), None));
+		try!(diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true))));
+		let mut langs = Vec::new();
+		for change in try!(get_changed_filenames(&diff)) {
+			if let Some(lang) = reviewed_file_language(&change.filename, settings) {
+				if !langs.contains(&lang.to_string()) {
+					langs.push(lang.to_string());
+				}
+			}
+		}
+		for lang in langs.iter() {
+			*commits_per_lang.entry(lang.clone()).or_insert(0) += 1;
+		}
+		say!("  {} {}: {}", &commit_id[.. 8], commit.summary().unwrap_or(""),
+			if langs.is_empty() { "no po changes".to_string() } else { langs.join(", ") });
+	}
+	for (lang, count) in commits_per_lang {
+		say!("{} touched by {} commit(s)", lang, count);
+	}
+	return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn po_with_charset(charset: &str, msgstr: &[u8]) -> Vec<u8> {
+		let mut cont = format!("msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset={}\\n\"\n\n\
+			msgid \"Hello\"\nmsgstr \"", charset).into_bytes();
+		cont.extend_from_slice(msgstr);
+		cont.extend_from_slice(b"\"\n");
+		return cont;
+	}
+
+	fn subject(kind: SubjectKind, msgctxt: Option<&str>, orig: &str, plural_index: Option<usize>) -> QuestionSubject {
+		let mut qs = QuestionSubject::default();
+		qs.kind = kind;
+		qs.commit_id = "0123abcd".to_string();
+		qs.from_filename = "po/de.po".to_string();
+		qs.orig = orig.to_string();
+		qs.entry.msgctxt = msgctxt.map(|c| c.to_string());
+		qs.entry.msgid = orig.to_string();
+		qs.plural_index = plural_index;
+		return qs;
+	}
+
+	fn subjects_between(commit_id: &str, old: Option<&str>, new: &str) -> Vec<QuestionSubject> {
+		let old_po = old.map(|cont| po::parse(cont).unwrap());
+		let new_po = po::parse(new).unwrap();
+		let mut res = Vec::new();
+		for (kind, old_entry, entry) in changed_entries(old_po.as_ref(), &new_po) {
+			if kind == SubjectKind::Obsoleted {
+				res.push(obsoleted_subject(commit_id, "po/de.po", old_entry.unwrap(), entry, None));
+				continue;
+			}
+			res.extend(subjects_for_entry(kind, commit_id, "po/de.po", old_po.is_some(), old_entry, entry, None, None));
+		}
+		return res;
+	}
+
+	fn ids(subjects: &[QuestionSubject]) -> Vec<String> {
+		return subjects.iter().map(|qs| qs.get_subject_id()).collect();
+	}
+
+	#[test]
+	fn legacy_keys() {
+		// keys as written by the line based parser of older versions
+		let subjects = subjects_between("0123abcd", None, "msgid \"\"\nmsgstr \"\"\n\n\
+			msgid \"Open file\"\nmsgstr \"Datei öffnen\"\n\n\
+			msgid \"\"\n\"Line one\\n\"\n\"say \\\"hi\\\"\\tnow\"\nmsgstr \"Zeile eins\\nsag \\\"hallo\\\"\\tjetzt\"\n");
+		assert_eq!(ids(&subjects), vec![
+			"0123abcd:po/de.po:Open file",
+			"0123abcd:po/de.po:Line one | say \\\"hi\\\"\\tnow",
+		]);
+		let subjects = subjects_between("0123abcd", None, "msgctxt \"menu\"\nmsgid \"Open\"\nmsgstr \"Öffnen\"\n");
+		assert_eq!(ids(&subjects), vec!["0123abcd:po/de.po:msgctxt \"menu\" Open"]);
+	}
+
+	#[test]
+	fn uncommitted_keys() {
+		let mut qs = subject(SubjectKind::Translation, None, "Open", None);
+		qs.commit_id = UNCOMMITTED_ID.to_string();
+		qs.old = Some("Öffnen".to_string());
+		qs.new = "Auf \"machen\"".to_string();
+		qs.entry.flags = vec!["fuzzy".to_string()];
+		assert_eq!(qs.get_subject_id(),
+			"uncommitted \"Öffnen\" -> \"Auf \\\"machen\\\"\" #, fuzzy:po/de.po:Open");
+		let fuzzy_id = qs.get_subject_id();
+		qs.entry.flags.clear();
+		assert!(qs.get_subject_id() != fuzzy_id);
+		qs.old = None;
+		assert_eq!(qs.get_subject_id(), "uncommitted none -> \"Auf \\\"machen\\\"\" #, :po/de.po:Open");
+	}
+
+	#[test]
+	fn entry_changes() {
+		let old = "msgid \"\"\nmsgstr \"\"\n\"Language: de\\n\"\n\n\
+			msgid \"Same\"\nmsgstr \"Gleich\"\n\n\
+			msgid \"Changed\"\nmsgstr \"Alt\"\n\n\
+			msgid \"Fuzzy\"\nmsgstr \"Unscharf\"\n\n\
+			msgid \"Old source\"\nmsgstr \"Quelle\"\n\n\
+			msgid \"Going\"\nmsgstr \"Geht\"\n\n\
+			#~ msgid \"Back\"\n#~ msgstr \"Zurück\"\n";
+		let new = "msgid \"\"\nmsgstr \"\"\n\"Language: fr\\n\"\n\n\
+			msgid \"Same\"\nmsgstr \"Gleich\"\n\n\
+			msgid \"Changed\"\nmsgstr \"Neu\"\n\n\
+			#, fuzzy\nmsgid \"Fuzzy\"\nmsgstr \"Unscharf\"\n\n\
+			#| msgid \"Old source\"\nmsgid \"New source\"\nmsgstr \"Quelle\"\n\n\
+			msgid \"Added\"\nmsgstr \"Neu\"\n\n\
+			msgid \"Back\"\nmsgstr \"Zurück\"\n\n\
+			#~ msgid \"Going\"\n#~ msgstr \"Geht\"\n";
+		let (old_po, new_po) = (po::parse(old).unwrap(), po::parse(new).unwrap());
+		let changes = changed_entries(Some(&old_po), &new_po).into_iter()
+			.map(|(kind, old_entry, entry)| (kind, old_entry.map(|e| e.msgid.clone()), entry.msgid.clone()))
+			.collect::<Vec<_>>();
+		let expected = vec![
+			(SubjectKind::Translation, Some("Changed"), "Changed"),
+			(SubjectKind::Translation, Some("Fuzzy"), "Fuzzy"),
+			(SubjectKind::Translation, Some("Old source"), "New source"),
+			(SubjectKind::Translation, None, "Added"),
+			(SubjectKind::Unobsoleted, Some("Back"), "Back"),
+			(SubjectKind::Obsoleted, Some("Going"), "Going"),
+		];
+		assert_eq!(changes.len(), expected.len());
+		for (change, expected) in changes.iter().zip(expected.iter()) {
+			assert!(change.0 == expected.0);
+			assert_eq!(change.1.as_ref().map(|id| id.as_ref()), expected.1);
+			assert_eq!(change.2, expected.2);
+		}
+		// without an old version, everything but the header and obsolete entries is new
+		assert_eq!(changed_entries(None, &new_po).len(), 6);
+	}
+
+	#[test]
+	fn combined_merge() {
+		let ours = "msgid \"a\"\nmsgstr \"A1\"\n\nmsgid \"b\"\nmsgstr \"B\"\n\nmsgid \"c\"\nmsgstr \"C1\"\n";
+		let theirs = "msgid \"a\"\nmsgstr \"A\"\n\nmsgid \"b\"\nmsgstr \"B2\"\n\nmsgid \"c\"\nmsgstr \"C2\"\n";
+		// a and b were changed on one side each, c on both and resolved by hand
+		let merge = "msgid \"a\"\nmsgstr \"A1\"\n\nmsgid \"b\"\nmsgstr \"B2\"\n\nmsgid \"c\"\nmsgstr \"C3\"\n";
+		let mut res = subjects_between("m", Some(ours), merge);
+		assert_eq!(ids(&res), vec!["m:po/de.po:b", "m:po/de.po:c"]);
+		retain_changed_against(&mut res, &subjects_between("m", Some(theirs), merge));
+		assert_eq!(ids(&res), vec!["m:po/de.po:c"]);
+		assert_eq!(res[0].new, "C3");
+		assert_eq!(res[0].old, Some("C1".to_string()));
+	}
+
+	#[test]
+	fn plural_keys() {
+		assert_eq!(subject(SubjectKind::Translation, None, "%d files", Some(1)).get_subject_id(),
+			"0123abcd:po/de.po:msgstr[1] \"%d files\"");
+		assert_eq!(subject(SubjectKind::Translation, Some("menu"), "%d files", Some(0)).get_subject_id(),
+			"0123abcd:po/de.po:msgctxt \"menu\" msgstr[0] \"%d files\"");
+		assert!(subject(SubjectKind::Translation, None, "foo", Some(0)).get_subject_id()
+			!= subject(SubjectKind::Translation, None, "foo[0]", None).get_subject_id());
+		assert!(subject(SubjectKind::Translation, None, "foo", Some(0)).get_subject_id()
+			!= subject(SubjectKind::Translation, None, "msgstr[0] \"foo\"", None).get_subject_id());
+	}
+
+	#[test]
+	fn file_keys() {
+		let header = subject(SubjectKind::Header, None, "", None);
+		assert_eq!(header.get_subject_id(), "0123abcd:po/de.po:msgid \"\" (header)");
+		assert!(header.get_subject_id() != subject(SubjectKind::Translation, None, "<header>", None).get_subject_id());
+		let mut rename = subject(SubjectKind::Rename, None, "", None);
+		rename.old = Some("po/old.po".to_string());
+		assert_eq!(rename.get_subject_id(), "0123abcd:po/de.po:renamed from \"po/old.po\"");
+		assert!(rename.get_subject_id()
+			!= subject(SubjectKind::Translation, None, "renamed from \"po/old.po\"", None).get_subject_id());
+	}
+
+	#[test]
+	fn charset_from_header() {
+		assert_eq!(header_charset(&po_with_charset("ISO-8859-1", b"")), Some("ISO-8859-1".to_string()));
+		assert_eq!(header_charset(b"\n\nmsgid \"\"\nmsgstr \"Content-Type: text/plain; charset=KOI8-R\"\n"),
+			Some("KOI8-R".to_string()));
+		assert_eq!(header_charset(b"msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain\\n\"\n"), None);
+		// only the header entry counts
+		assert_eq!(header_charset(b"msgid \"\"\nmsgstr \"\"\n\nmsgid \"Content-Type: text/plain; charset=KOI8-R\"\nmsgstr \"\"\n"), None);
+	}
+
+	#[test]
+	fn decoding() {
+		let po = blob_parser(&po_with_charset("ISO-8859-1", b"Gr\xfc\xdfe"), "de.po").unwrap();
+		assert_eq!(po.find(None, "Hello").unwrap().msgstr, vec!["Grüße"]);
+		let po = blob_parser(&po_with_charset("KOI8-R", b"\xf0\xd2\xc9\xd7\xc5\xd4"), "ru.po").unwrap();
+		assert_eq!(po.find(None, "Hello").unwrap().msgstr, vec!["Привет"]);
+		let po = blob_parser(&po_with_charset("UTF-8", "Grüße".as_bytes()), "de.po").unwrap();
+		assert_eq!(po.find(None, "Hello").unwrap().msgstr, vec!["Grüße"]);
+		assert!(blob_parser(&po_with_charset("UTF-8", b"Gr\xfc\xdfe"), "de.po").is_err());
+		assert!(blob_parser(&po_with_charset("no-such-charset", b""), "de.po").is_err());
+	}
+
+	#[test]
+	fn reference_paths() {
+		assert_eq!(reference_path_candidates("po/de.po", "src/main.c"), vec!["src/main.c", "po/src/main.c"]);
+		assert_eq!(reference_path_candidates("po/de.po", "../src/main.c"), vec!["src/main.c"]);
+		assert_eq!(reference_path_candidates("po/de.po", "./main.c"), vec!["main.c", "po/main.c"]);
+		assert!(reference_path_candidates("po/de.po", "../../secret").is_empty());
+		assert!(reference_path_candidates("po/de.po", "/home/reviewer/.ssh/id_rsa").is_empty());
+		assert!(reference_path_candidates("de.po", "..").is_empty());
+	}
+
+	#[test]
+	fn workdir_stays_in_repo() {
+		let dir = env::temp_dir().join(format!("podiff-review-test-{}", process::id()));
+		let workdir = dir.join("repo");
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&workdir).unwrap();
+		File::create(dir.join("secret")).unwrap().write_all(b"secret").unwrap();
+		File::create(workdir.join("main.c")).unwrap().write_all(b"code").unwrap();
+		assert_eq!(read_workdir_file(&workdir, "main.c").unwrap(), b"code");
+		assert!(read_workdir_file(&workdir, "../secret").is_err());
+		assert!(read_workdir_file(&workdir, dir.join("secret").to_str().unwrap()).is_err());
+		#[cfg(unix)]
+		{
+			::std::os::unix::fs::symlink(dir.join("secret"), workdir.join("link")).unwrap();
+			assert!(read_workdir_file(&workdir, "link").is_err());
+		}
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn escapes_in_charset() {
+		let po = blob_parser(&po_with_charset("ISO-8859-1", b"\\344"), "de.po").unwrap();
+		assert_eq!(po.find(None, "Hello").unwrap().msgstr, vec!["ä"]);
+		let po = blob_parser(&po_with_charset("UTF-8", b"\\303\\244"), "de.po").unwrap();
+		assert_eq!(po.find(None, "Hello").unwrap().msgstr, vec!["ä"]);
+		assert!(blob_parser(&po_with_charset("UTF-8", b"\\344"), "de.po").is_err());
+	}
 }
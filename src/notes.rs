@@ -0,0 +1,82 @@
+// podiff-review
+// review po changes easily
+//
+// The MIT License (MIT)
+//
+// Copyright 2015 est31 <MTest31@outlook.com>
+/*
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+*/
+
+use git2::{Repository, Oid, Error};
+use toml;
+
+/// Reads the verdicts stored in the note for the given commit.
+/// Returns an empty table if the commit has no note.
+pub fn read_verdicts(repo: &Repository, notes_ref: &str, commit_id: Oid) -> Result<toml::Table, Error> {
+	let note = match repo.find_note(Some(notes_ref), commit_id) {
+		Ok(note) => note,
+		Err(_) => return Ok(toml::Table::new()),
+	};
+	return parse_verdicts(note.message().unwrap_or(""))
+		.map_err(|e| Error::from_str(&format!("Could not parse note of {} in {}: {}", commit_id, notes_ref, e)));
+}
+
+/// Returns the verdicts of a note, leaving out any that aren't true or false
+fn parse_verdicts(cont: &str) -> Result<toml::Table, String> {
+	let mut parser = toml::Parser::new(cont);
+	let mut tbl = match parser.parse() {
+		Some(tbl) => tbl,
+		None => return Err(format!("{:?}", parser.errors)),
+	};
+	return Ok(match tbl.remove("verdicts") {
+		Some(toml::Value::Table(verdicts)) => verdicts.into_iter()
+			.filter(|&(_, ref v)| v.as_bool().is_some())
+			.collect(),
+		_ => toml::Table::new(),
+	});
+}
+
+/// Writes the overall result and the verdicts of a commit review into its note,
+/// replacing any earlier note.
+pub fn write_verdicts(repo: &Repository, notes_ref: &str, commit_id: Oid, approved: bool, verdicts: toml::Table) -> Result<(), Error> {
+	let mut tbl = toml::Table::new();
+	tbl.insert("approved".to_string(), toml::Value::Boolean(approved));
+	tbl.insert("verdicts".to_string(), toml::Value::Table(verdicts));
+	let sig = try!(repo.signature());
+	try!(repo.note(&sig, &sig, Some(notes_ref), commit_id, &toml::Value::Table(tbl).to_string(), true));
+	return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use toml;
+
+	#[test]
+	fn verdicts() {
+		let verdicts = parse_verdicts("approved = false\n[verdicts]\n\"a\" = true\n\"b\" = false\n\"c\" = \"maybe\"\n").unwrap();
+		assert_eq!(verdicts.get("a"), Some(&toml::Value::Boolean(true)));
+		assert_eq!(verdicts.get("b"), Some(&toml::Value::Boolean(false)));
+		assert_eq!(verdicts.len(), 2);
+		assert!(parse_verdicts("Reviewed-by: someone else").is_err());
+		assert!(parse_verdicts("verdicts = 1").unwrap().is_empty());
+		assert!(parse_verdicts("").unwrap().is_empty());
+	}
+}
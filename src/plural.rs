@@ -0,0 +1,400 @@
+// podiff-review
+// review po changes easily
+//
+// The MIT License (MIT)
+//
+// Copyright 2015 est31 <MTest31@outlook.com>
+/*
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+*/
+
+use std::fmt;
+
+macro_rules! try_opt {
+	($expr:expr) => (match $expr {
+		Some(val) => val,
+		None => return None,
+	})
+}
+
+/// A parsed "nplurals=...; plural=...;" header value
+#[derive(Clone, Debug)]
+pub struct PluralForms {
+	pub nplurals: usize,
+	pub formula: String,
+	expr: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+	N,
+	Num(u64),
+	Not(Box<Expr>),
+	Bin(BinOp, Box<Expr>, Box<Expr>),
+	Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp {
+	Mul, Div, Rem,
+	Add, Sub,
+	Lt, Gt, Le, Ge,
+	Eq, Ne,
+	And, Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+	N,
+	Num(u64),
+	Op(BinOp),
+	Not,
+	Question,
+	Colon,
+	LParen,
+	RParen,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+	let mut res = Vec::new();
+	let mut chars = s.chars().peekable();
+	while let Some(c) = chars.next() {
+		let next = chars.peek().cloned();
+		let tok = match (c, next) {
+			(c, _) if c.is_whitespace() => continue,
+			('n', _) => Token::N,
+			(c, _) if c.is_digit(10) => {
+				let mut val = c.to_digit(10).unwrap() as u64;
+				while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+					val = val * 10 + d as u64;
+					chars.next();
+				}
+				Token::Num(val)
+			},
+			('<', Some('=')) => { chars.next(); Token::Op(BinOp::Le) },
+			('>', Some('=')) => { chars.next(); Token::Op(BinOp::Ge) },
+			('=', Some('=')) => { chars.next(); Token::Op(BinOp::Eq) },
+			('!', Some('=')) => { chars.next(); Token::Op(BinOp::Ne) },
+			('&', Some('&')) => { chars.next(); Token::Op(BinOp::And) },
+			('|', Some('|')) => { chars.next(); Token::Op(BinOp::Or) },
+			('<', _) => Token::Op(BinOp::Lt),
+			('>', _) => Token::Op(BinOp::Gt),
+			('*', _) => Token::Op(BinOp::Mul),
+			('/', _) => Token::Op(BinOp::Div),
+			('%', _) => Token::Op(BinOp::Rem),
+			('+', _) => Token::Op(BinOp::Add),
+			('-', _) => Token::Op(BinOp::Sub),
+			('!', _) => Token::Not,
+			('?', _) => Token::Question,
+			(':', _) => Token::Colon,
+			('(', _) => Token::LParen,
+			(')', _) => Token::RParen,
+			(c, _) => return Err(format!("unexpected character '{}' in plural formula", c)),
+		};
+		res.push(tok);
+	}
+	return Ok(res);
+}
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize,
+	depth: usize,
+}
+
+// The formula comes from the reviewed file, so limit how deep parsing and
+// evaluating it may recurse. Real formulas stay far below these.
+const MAX_DEPTH: usize = 100;
+const MAX_TOKENS: usize = 1000;
+
+// Binary operator levels, from lowest to highest precedence
+const LEVELS: &'static [&'static [BinOp]] = &[
+	&[BinOp::Or],
+	&[BinOp::And],
+	&[BinOp::Eq, BinOp::Ne],
+	&[BinOp::Lt, BinOp::Gt, BinOp::Le, BinOp::Ge],
+	&[BinOp::Add, BinOp::Sub],
+	&[BinOp::Mul, BinOp::Div, BinOp::Rem],
+];
+
+impl Parser {
+	fn peek(&self) -> Option<Token> {
+		return self.tokens.get(self.pos).cloned();
+	}
+	fn expect(&mut self, tok: Token) -> Result<(), String> {
+		if self.peek() == Some(tok) {
+			self.pos += 1;
+			return Ok(());
+		}
+		return Err(format!("expected {:?} in plural formula", tok));
+	}
+	fn ternary(&mut self) -> Result<Expr, String> {
+		self.depth += 1;
+		if self.depth > MAX_DEPTH {
+			return Err("plural formula is nested too deeply".to_string());
+		}
+		let res = self.ternary_inner();
+		self.depth -= 1;
+		return res;
+	}
+	fn ternary_inner(&mut self) -> Result<Expr, String> {
+		let cond = try!(self.binary(0));
+		if self.peek() != Some(Token::Question) {
+			return Ok(cond);
+		}
+		self.pos += 1;
+		let then = try!(self.ternary());
+		try!(self.expect(Token::Colon));
+		let els = try!(self.ternary());
+		return Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(els)));
+	}
+	fn binary(&mut self, level: usize) -> Result<Expr, String> {
+		if level == LEVELS.len() {
+			return self.unary();
+		}
+		let mut lhs = try!(self.binary(level + 1));
+		loop {
+			let op = match self.peek() {
+				Some(Token::Op(op)) if LEVELS[level].contains(&op) => op,
+				_ => return Ok(lhs),
+			};
+			self.pos += 1;
+			let rhs = try!(self.binary(level + 1));
+			lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs));
+		}
+	}
+	fn unary(&mut self) -> Result<Expr, String> {
+		// negations are counted instead of recursed into
+		let mut nots = 0;
+		while self.peek() == Some(Token::Not) {
+			self.pos += 1;
+			nots += 1;
+		}
+		let mut expr = try!(self.primary());
+		for _ in 0 .. nots {
+			expr = Expr::Not(Box::new(expr));
+		}
+		return Ok(expr);
+	}
+	fn primary(&mut self) -> Result<Expr, String> {
+		let tok = self.peek();
+		self.pos += 1;
+		return match tok {
+			Some(Token::N) => Ok(Expr::N),
+			Some(Token::Num(v)) => Ok(Expr::Num(v)),
+			Some(Token::LParen) => {
+				let inner = try!(self.ternary());
+				try!(self.expect(Token::RParen));
+				Ok(inner)
+			},
+			Some(tok) => Err(format!("unexpected {:?} in plural formula", tok)),
+			None => Err("plural formula ends unexpectedly".to_string()),
+		};
+	}
+}
+
+impl Expr {
+	fn eval(&self, n: u64) -> Option<u64> {
+		return Some(match *self {
+			Expr::N => n,
+			Expr::Num(v) => v,
+			Expr::Not(ref e) => (try_opt!(e.eval(n)) == 0) as u64,
+			Expr::Cond(ref c, ref t, ref e) => if try_opt!(c.eval(n)) != 0 {
+				try_opt!(t.eval(n))
+			} else {
+				try_opt!(e.eval(n))
+			},
+			Expr::Bin(op, ref l, ref r) => {
+				let l = try_opt!(l.eval(n));
+				// Short circuit like C does
+				match op {
+					BinOp::And if l == 0 => return Some(0),
+					BinOp::Or if l != 0 => return Some(1),
+					_ => (),
+				}
+				let r = try_opt!(r.eval(n));
+				match op {
+					BinOp::Mul => l.wrapping_mul(r),
+					BinOp::Div => try_opt!(l.checked_div(r)),
+					BinOp::Rem => try_opt!(l.checked_rem(r)),
+					BinOp::Add => l.wrapping_add(r),
+					BinOp::Sub => l.wrapping_sub(r),
+					BinOp::Lt => (l < r) as u64,
+					BinOp::Gt => (l > r) as u64,
+					BinOp::Le => (l <= r) as u64,
+					BinOp::Ge => (l >= r) as u64,
+					BinOp::Eq => (l == r) as u64,
+					BinOp::Ne => (l != r) as u64,
+					BinOp::And | BinOp::Or => (r != 0) as u64,
+				}
+			},
+		});
+	}
+}
+
+impl PluralForms {
+	/// Parses a Plural-Forms header value like "nplurals=2; plural=(n != 1);"
+	pub fn parse(s: &str) -> Result<PluralForms, String> {
+		let mut nplurals = None;
+		let mut formula = None;
+		for part in s.split(';') {
+			let mut kv = part.splitn(2, '=');
+			let key = kv.next().unwrap().trim();
+			let val = match kv.next() {
+				Some(val) => val.trim(),
+				None => continue,
+			};
+			match key {
+				"nplurals" => nplurals = Some(try!(val.parse::<usize>()
+					.map_err(|_| format!("invalid nplurals value '{}'", val)))),
+				"plural" => formula = Some(val.to_string()),
+				_ => (),
+			}
+		}
+		let nplurals = try!(nplurals.ok_or("nplurals missing in Plural-Forms".to_string()));
+		let formula = try!(formula.ok_or("plural missing in Plural-Forms".to_string()));
+		let tokens = try!(tokenize(&formula));
+		if tokens.len() > MAX_TOKENS {
+			return Err(format!("plural formula is longer than {} tokens", MAX_TOKENS));
+		}
+		let mut parser = Parser { tokens: tokens, pos: 0, depth: 0 };
+		let expr = try!(parser.ternary());
+		if parser.pos != parser.tokens.len() {
+			return Err(format!("trailing garbage in plural formula '{}'", formula));
+		}
+		return Ok(PluralForms { nplurals: nplurals, formula: formula, expr: expr });
+	}
+
+	/// Returns the index of the plural form to use for the number n
+	pub fn index_for(&self, n: u64) -> Option<usize> {
+		return self.expr.eval(n).map(|v| v as usize);
+	}
+
+	/// Returns up to max_count numbers that select the plural form with the given index
+	pub fn examples(&self, idx: usize, max_count: usize) -> Vec<u64> {
+		return (0 .. 1000).filter(|n| self.index_for(*n) == Some(idx))
+			.take(max_count).collect();
+	}
+
+	/// Describes for which numbers the plural form with the given index is used
+	pub fn describe(&self, idx: usize) -> String {
+		let examples = self.examples(idx, 6);
+		if examples.is_empty() {
+			return format!("form {} (not used by {})", idx, self.formula);
+		}
+		let mut list = examples.iter().take(5).map(|n| n.to_string()).collect::<Vec<_>>().join(", ");
+		if examples.len() > 5 {
+			list.push_str(", ...");
+		}
+		return format!("form {} (n = {})", idx, list);
+	}
+}
+
+impl fmt::Display for PluralForms {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "nplurals={}; plural={};", self.nplurals, self.formula)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn forms(s: &str) -> PluralForms {
+		return PluralForms::parse(s).unwrap();
+	}
+
+	fn indices(pf: &PluralForms, ns: &[u64]) -> Vec<Option<usize>> {
+		return ns.iter().map(|n| pf.index_for(*n)).collect();
+	}
+
+	#[test]
+	fn tokens() {
+		assert_eq!(tokenize("n%10>=2&&!(n<5)").unwrap(), vec![Token::N,
+			Token::Op(BinOp::Rem), Token::Num(10), Token::Op(BinOp::Ge), Token::Num(2),
+			Token::Op(BinOp::And), Token::Not, Token::LParen, Token::N,
+			Token::Op(BinOp::Lt), Token::Num(5), Token::RParen]);
+		assert!(tokenize("n = 1").is_err());
+	}
+
+	#[test]
+	fn precedence() {
+		// Would be 0 for n = 1 if evaluated left to right
+		assert_eq!(forms("nplurals=2; plural=1 + n * 2 == 3;").index_for(1), Some(1));
+		assert_eq!(forms("nplurals=2; plural=n - 2 - 1;").index_for(5), Some(2));
+		assert_eq!(forms("nplurals=2; plural=n == 1 || n == 2 && 0;").index_for(1), Some(1));
+		assert_eq!(forms("nplurals=2; plural=!n == 0;").index_for(3), Some(1));
+	}
+
+	#[test]
+	fn common_formulas() {
+		let ns = [0, 1, 2, 5, 11, 21, 22, 25, 111];
+		let de = forms("nplurals=2; plural=(n != 1);");
+		assert_eq!(indices(&de, &ns), vec![Some(1), Some(0), Some(1), Some(1), Some(1),
+			Some(1), Some(1), Some(1), Some(1)]);
+		let ru = forms("nplurals=3; plural=(n%10==1 && n%100!=11 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);");
+		assert_eq!(indices(&ru, &ns), vec![Some(2), Some(0), Some(1), Some(2), Some(2),
+			Some(0), Some(1), Some(2), Some(2)]);
+		let pl = forms("nplurals=3; plural=(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);");
+		assert_eq!(indices(&pl, &ns), vec![Some(2), Some(0), Some(1), Some(2), Some(2),
+			Some(2), Some(1), Some(2), Some(2)]);
+		assert_eq!(forms("nplurals=1; plural=0;").index_for(7), Some(0));
+	}
+
+	#[test]
+	fn division_by_zero() {
+		assert_eq!(forms("nplurals=2; plural=n / 0;").index_for(1), None);
+		assert_eq!(forms("nplurals=2; plural=n && 1 % n;").index_for(0), Some(0));
+	}
+
+	#[test]
+	fn examples_and_describe() {
+		let ru = forms("nplurals=3; plural=(n%10==1 && n%100!=11 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2);");
+		assert_eq!(ru.examples(0, 3), vec![1, 21, 31]);
+		assert_eq!(ru.describe(1), "form 1 (n = 2, 3, 4, 22, 23, ...)");
+		let fr = forms("nplurals=2; plural=(n > 1);");
+		assert_eq!(fr.examples(0, 5), vec![0, 1]);
+		assert_eq!(fr.describe(2), "form 2 (not used by (n > 1))");
+		assert_eq!(fr.to_string(), "nplurals=2; plural=(n > 1);");
+	}
+
+	#[test]
+	fn parse_errors() {
+		assert!(PluralForms::parse("plural=(n != 1);").is_err());
+		assert!(PluralForms::parse("nplurals=two; plural=(n != 1);").is_err());
+		assert!(PluralForms::parse("nplurals=2;").is_err());
+		assert!(PluralForms::parse("nplurals=2; plural=(n != 1;").is_err());
+		assert!(PluralForms::parse("nplurals=2; plural=n ? 1;").is_err());
+		assert!(PluralForms::parse("nplurals=2; plural=n 1;").is_err());
+		assert!(PluralForms::parse("nplurals=2; plural=;").is_err());
+	}
+
+	#[test]
+	fn nesting_limit() {
+		let nested = |open: &str, close: &str, count| format!("nplurals=2; plural={}n{};",
+			open.repeat(count), close.repeat(count));
+		assert_eq!(forms(&nested("(", ")", 50)).index_for(1), Some(1));
+		assert!(PluralForms::parse(&nested("(", ")", 150)).is_err());
+		assert!(PluralForms::parse(&nested("(", ")", 200000)).is_err());
+		assert!(PluralForms::parse(&nested("!", "", 200000)).is_err());
+		assert_eq!(forms(&nested("!", "", 3)).index_for(1), Some(0));
+		assert!(PluralForms::parse(&nested("n ? 0 : ", "", 200000)).is_err());
+		assert!(PluralForms::parse(&nested("n + ", "", 200000)).is_err());
+	}
+}
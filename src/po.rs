@@ -0,0 +1,604 @@
+// podiff-review
+// review po changes easily
+//
+// The MIT License (MIT)
+//
+// Copyright 2015 est31 <MTest31@outlook.com>
+/*
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+*/
+
+use std::fmt;
+use plural::PluralForms;
+
+/// A single entry of a po file, with everything that belongs to it.
+///
+/// Strings are stored with their escape sequences decoded.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct PoEntry {
+	/// "# " comments, written by translators
+	pub translator_comments: Vec<String>,
+	/// "#." comments, extracted from the source code
+	pub extracted_comments: Vec<String>,
+	/// "#:" source references, like "src/main.c:42"
+	pub references: Vec<String>,
+	/// "#," flags, like "fuzzy" or "c-format"
+	pub flags: Vec<String>,
+	/// "#| msgctxt", the msgctxt before the source text changed
+	pub previous_msgctxt: Option<String>,
+	/// "#| msgid", the msgid before the source text changed
+	pub previous_msgid: Option<String>,
+	/// "#| msgid_plural", the msgid_plural before the source text changed
+	pub previous_msgid_plural: Option<String>,
+	pub msgctxt: Option<String>,
+	pub msgid: String,
+	pub msgid_plural: Option<String>,
+	/// The translations. Entries without msgid_plural have exactly one.
+	pub msgstr: Vec<String>,
+	/// Whether the entry was commented out with "#~"
+	pub obsolete: bool,
+}
+
+impl PoEntry {
+	pub fn is_header(&self) -> bool {
+		return self.msgid == "" && self.msgctxt.is_none() && !self.obsolete;
+	}
+	pub fn has_flag(&self, flag: &str) -> bool {
+		return self.flags.iter().any(|f| f == flag);
+	}
+
+	/// Splits the msgstr of the header entry into its "Name: value" fields
+	pub fn header_fields(&self) -> Vec<(String, String)> {
+		let cont = match self.msgstr.first() {
+			Some(cont) => cont,
+			None => return Vec::new(),
+		};
+		return cont.lines().filter_map(|line| {
+			let mut kv = line.splitn(2, ':');
+			let key = kv.next().unwrap().trim();
+			match kv.next() {
+				Some(val) if key != "" => Some((key.to_string(), val.trim().to_string())),
+				_ => None,
+			}
+		}).collect();
+	}
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct PoFile {
+	/// All entries in file order, including the header and obsolete ones
+	pub entries: Vec<PoEntry>,
+}
+
+impl PoFile {
+	pub fn header(&self) -> Option<&PoEntry> {
+		return self.entries.iter().find(|e| e.is_header());
+	}
+
+	/// Returns the value of the given header field
+	pub fn header_field(&self, name: &str) -> Option<String> {
+		return self.header().and_then(|h| h.header_fields().into_iter()
+			.find(|&(ref k, _)| k.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v));
+	}
+
+	/// Returns the charset declared in the Content-Type header field
+	pub fn charset(&self) -> Option<String> {
+		return self.header_field("Content-Type").and_then(|ct| ct.split(';')
+			.map(|p| p.trim())
+			.find(|p| p.to_lowercase().starts_with("charset="))
+			.map(|p| p["charset=".len() ..].trim().to_string()));
+	}
+
+	/// Parses the Plural-Forms header field, if present
+	pub fn plural_forms(&self) -> Option<Result<PluralForms, String>> {
+		return self.header_field("Plural-Forms").map(|v| PluralForms::parse(&v));
+	}
+
+	/// Finds the non-obsolete entry with the given msgctxt and msgid
+	pub fn find(&self, msgctxt: Option<&str>, msgid: &str) -> Option<&PoEntry> {
+		return self.find_entry(msgctxt, msgid, false);
+	}
+
+	/// Finds the obsolete ("#~") entry with the given msgctxt and msgid
+	pub fn find_obsolete(&self, msgctxt: Option<&str>, msgid: &str) -> Option<&PoEntry> {
+		return self.find_entry(msgctxt, msgid, true);
+	}
+
+	fn find_entry(&self, msgctxt: Option<&str>, msgid: &str, obsolete: bool) -> Option<&PoEntry> {
+		return self.entries.iter().find(|e| e.obsolete == obsolete
+			&& e.msgctxt.as_ref().map(|c| c.as_ref()) == msgctxt && e.msgid == msgid);
+	}
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+	pub line: usize,
+	pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.msg)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+	Msgctxt,
+	Msgid,
+	MsgidPlural,
+	Msgstr(usize),
+}
+
+fn decode_utf8(bytes: &[u8]) -> Result<String, String> {
+	return String::from_utf8(bytes.to_vec()).map_err(|e| format!("not valid UTF-8: {}", e));
+}
+
+/// Decodes the C-style escape sequences of a po string
+pub fn unescape(s: &str) -> Result<String, String> {
+	return unescape_with(s, &decode_utf8);
+}
+
+/// Like unescape, but the bytes given by escape sequences (like "\344")
+/// are decoded with decode, for files that are not in UTF-8
+pub fn unescape_with(s: &str, decode: &Fn(&[u8]) -> Result<String, String>) -> Result<String, String> {
+	let mut res = String::with_capacity(s.len());
+	// bytes of consecutive escape sequences, which may form a single character together
+	let mut bytes = Vec::new();
+	let mut chars = s.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			if !bytes.is_empty() {
+				res.push_str(&try!(decode(&bytes).map_err(|e| format!("escape sequences are {}", e))));
+				bytes.clear();
+			}
+			res.push(c);
+			continue;
+		}
+		let esc = match chars.next() {
+			Some(esc) => esc,
+			None => return Err("string ends with a lone backslash".to_string()),
+		};
+		match esc {
+			'n' => bytes.push(b'\n'),
+			't' => bytes.push(b'\t'),
+			'r' => bytes.push(b'\r'),
+			'a' => bytes.push(0x07),
+			'b' => bytes.push(0x08),
+			'f' => bytes.push(0x0c),
+			'v' => bytes.push(0x0b),
+			'\\' | '"' | '\'' | '?' => bytes.push(esc as u8),
+			'0' ... '7' => {
+				let mut val = esc.to_digit(8).unwrap();
+				for _ in 0 .. 2 {
+					match chars.peek().and_then(|c| c.to_digit(8)) {
+						Some(d) => { val = val * 8 + d; chars.next(); },
+						None => break,
+					}
+				}
+				if val > 0xff {
+					return Err(format!("octal escape \\{:o} out of range", val));
+				}
+				bytes.push(val as u8);
+			},
+			'x' => {
+				let mut val = 0;
+				let mut digits = 0;
+				while let Some(d) = chars.peek().and_then(|c| c.to_digit(16)) {
+					val = val * 16 + d;
+					digits += 1;
+					chars.next();
+					if digits > 2 {
+						return Err("hex escape out of range".to_string());
+					}
+				}
+				if digits == 0 {
+					return Err("hex escape without digits".to_string());
+				}
+				bytes.push(val as u8);
+			},
+			_ => return Err(format!("unknown escape sequence \\{}", esc)),
+		}
+	}
+	if !bytes.is_empty() {
+		res.push_str(&try!(decode(&bytes).map_err(|e| format!("escape sequences are {}", e))));
+	}
+	return Ok(res);
+}
+
+/// Encodes a string the way it would be written between the quotes in a po file
+pub fn escape(s: &str) -> String {
+	let mut res = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'\n' => res.push_str("\\n"),
+			'\t' => res.push_str("\\t"),
+			'\r' => res.push_str("\\r"),
+			'"' => res.push_str("\\\""),
+			'\\' => res.push_str("\\\\"),
+			c if (c as u32) < 0x20 || c == '\x7f' => res.push_str(&format!("\\{:03o}", c as u32)),
+			c => res.push(c),
+		}
+	}
+	return res;
+}
+
+/// Makes control characters visible for displaying a string to the user
+pub fn visible(s: &str) -> String {
+	let mut res = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'\n' => res.push_str("\u{21b5}"), // ↵
+			'\t' => res.push_str("\u{21e5}"), // ⇥
+			c if (c as u32) < 0x20 => res.push(::std::char::from_u32(0x2400 + c as u32).unwrap()),
+			'\x7f' => res.push('\u{2421}'),
+			c => res.push(c),
+		}
+	}
+	return res;
+}
+
+/// Returns the decoded content of a "quoted" string
+fn parse_quoted(s: &str, decode: &Fn(&[u8]) -> Result<String, String>) -> Result<String, String> {
+	let s = s.trim();
+	if s.len() < 2 || !s.starts_with("\"") || !s.ends_with("\"") {
+		return Err(format!("expected quoted string, found {}", s));
+	}
+	let inner = &s[1 .. s.len() - 1];
+	// the closing quote must not be escaped
+	let trailing_backslashes = inner.chars().rev().take_while(|c| *c == '\\').count();
+	if trailing_backslashes % 2 == 1 {
+		return Err(format!("unterminated string {}", s));
+	}
+	return unescape_with(inner, decode);
+}
+
+fn comment_text(line: &str, prefix_len: usize) -> String {
+	let rest = &line[prefix_len ..];
+	return rest.trim_right().trim_left_matches(' ').to_string();
+}
+
+/// Parses the content of a po file
+pub fn parse(cont: &str) -> Result<PoFile, ParseError> {
+	return parse_with_decoder(cont, &decode_utf8);
+}
+
+/// Parses the content of a po file, decoding the bytes of escape sequences
+/// with decode, which should use the charset of the file
+pub fn parse_with_decoder(cont: &str, decode: &Fn(&[u8]) -> Result<String, String>) -> Result<PoFile, ParseError> {
+	let mut res = PoFile::default();
+	let mut cur = PoEntry::default();
+	let mut seen_msgid = false;
+	let mut last_field: Option<Field> = None;
+	let mut last_previous_field: Option<Field> = None;
+	let mut line_no = 0;
+
+	macro_rules! err {
+		($($arg:tt)*) => {
+			return Err(ParseError { line: line_no, msg: format!($($arg)*) })
+		}
+	}
+	macro_rules! finish_entry {
+		() => { {
+			if seen_msgid {
+				if cur.msgstr.is_empty() {
+					err!("msgid \"{}\" has no msgstr", cur.msgid);
+				}
+				res.entries.push(cur);
+			}
+			cur = PoEntry::default();
+			seen_msgid = false;
+			last_field = None;
+			last_previous_field = None;
+		} }
+	}
+	macro_rules! field_mut {
+		($field:expr) => {
+			match $field {
+				Field::Msgctxt => cur.msgctxt.as_mut().unwrap(),
+				Field::Msgid => &mut cur.msgid,
+				Field::MsgidPlural => cur.msgid_plural.as_mut().unwrap(),
+				Field::Msgstr(i) => &mut cur.msgstr[i],
+			}
+		}
+	}
+
+	for line in cont.lines() {
+		line_no += 1;
+		let mut line = line.trim();
+		let mut obsolete = false;
+		if line.starts_with("#~") {
+			obsolete = true;
+			line = line["#~".len() ..].trim_left();
+		}
+		if line.is_empty() {
+			if !obsolete {
+				finish_entry!();
+			}
+			continue;
+		}
+		let after_msgstr = match last_field { Some(Field::Msgstr(_)) => true, _ => false };
+
+		// "#|" lines, or "#~|" for obsolete entries
+		let previous_line = if line.starts_with("#|") {
+			Some(line["#|".len() ..].trim())
+		} else if obsolete && line.starts_with("|") {
+			Some(line["|".len() ..].trim())
+		} else {
+			None
+		};
+		if let Some(line) = previous_line {
+			if after_msgstr {
+				finish_entry!();
+			}
+			let continuation = line.starts_with("\"");
+			let (field, s) = if continuation {
+				match last_previous_field {
+					Some(field) => (field, line),
+					None => err!("previous string continuation without keyword"),
+				}
+			} else {
+				match line.find(|c: char| c.is_whitespace()) {
+					Some(pos) => (match &line[.. pos] {
+						"msgctxt" => Field::Msgctxt,
+						"msgid" => Field::Msgid,
+						"msgid_plural" => Field::MsgidPlural,
+						kw => err!("unknown keyword #| {}", kw),
+					}, &line[pos ..]),
+					None => err!("keyword without string: #| {}", line),
+				}
+			};
+			let s = match parse_quoted(s, decode) {
+				Ok(s) => s,
+				Err(e) => err!("{} in previous string", e),
+			};
+			let target = match field {
+				Field::Msgctxt => &mut cur.previous_msgctxt,
+				Field::Msgid => &mut cur.previous_msgid,
+				_ => &mut cur.previous_msgid_plural,
+			};
+			match *target {
+				Some(ref mut v) if continuation => v.push_str(&s),
+				_ => *target = Some(s),
+			}
+			last_previous_field = Some(field);
+			continue;
+		}
+
+		if line.starts_with("#") {
+			if after_msgstr {
+				finish_entry!();
+			}
+			if line.starts_with("#.") {
+				cur.extracted_comments.push(comment_text(line, 2));
+			} else if line.starts_with("#:") {
+				cur.references.extend(line[2 ..].split_whitespace().map(|s| s.to_string()));
+			} else if line.starts_with("#,") {
+				cur.flags.extend(line[2 ..].split(',')
+					.map(|s| s.trim())
+					.filter(|s| !s.is_empty())
+					.map(|s| s.to_string()));
+			} else {
+				cur.translator_comments.push(comment_text(line, 1));
+			}
+			continue;
+		}
+
+		if line.starts_with("\"") {
+			let s = match parse_quoted(line, decode) {
+				Ok(s) => s,
+				Err(e) => err!("{}", e),
+			};
+			match last_field {
+				Some(field) => field_mut!(field).push_str(&s),
+				None => err!("string continuation without keyword"),
+			}
+			continue;
+		}
+
+		let (keyword, rest) = match line.find(|c: char| c.is_whitespace()) {
+			Some(pos) => (&line[.. pos], &line[pos ..]),
+			None => err!("keyword without string: {}", line),
+		};
+		let s = match parse_quoted(rest, decode) {
+			Ok(s) => s,
+			Err(e) => err!("{} after {}", e, keyword),
+		};
+		let field = match keyword {
+			"msgctxt" => {
+				if seen_msgid {
+					finish_entry!();
+				}
+				cur.msgctxt = Some(s);
+				Field::Msgctxt
+			},
+			"msgid" => {
+				if seen_msgid {
+					finish_entry!();
+				}
+				seen_msgid = true;
+				cur.msgid = s;
+				Field::Msgid
+			},
+			"msgid_plural" => {
+				if last_field != Some(Field::Msgid) {
+					err!("msgid_plural not directly after msgid");
+				}
+				cur.msgid_plural = Some(s);
+				Field::MsgidPlural
+			},
+			"msgstr" => {
+				if !seen_msgid {
+					err!("msgstr without msgid");
+				}
+				if !cur.msgstr.is_empty() {
+					err!("duplicate msgstr for msgid \"{}\"", cur.msgid);
+				}
+				cur.msgstr.push(s);
+				Field::Msgstr(0)
+			},
+			kw if kw.starts_with("msgstr[") && kw.ends_with("]") => {
+				if !seen_msgid {
+					err!("{} without msgid", kw);
+				}
+				let idx = match kw["msgstr[".len() .. kw.len() - 1].parse::<usize>() {
+					Ok(idx) => idx,
+					Err(_) => err!("invalid plural index in {}", kw),
+				};
+				if idx != cur.msgstr.len() {
+					err!("{} out of order for msgid \"{}\"", kw, cur.msgid);
+				}
+				cur.msgstr.push(s);
+				Field::Msgstr(idx)
+			},
+			kw => err!("unknown keyword {}", kw),
+		};
+		if obsolete {
+			cur.obsolete = true;
+		}
+		last_field = Some(field);
+	}
+	line_no += 1;
+	finish_entry!();
+	return Ok(res);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unescape_sequences() {
+		assert_eq!(unescape("a\\nb\\t\\\"c\\\\").unwrap(), "a\nb\t\"c\\");
+		assert_eq!(unescape("\\101\\x42\\0").unwrap(), "AB\0");
+		assert_eq!(unescape("\\303\\244").unwrap(), "ä");
+		assert!(unescape("trailing\\").is_err());
+		assert!(unescape("\\q").is_err());
+		assert!(unescape("\\x").is_err());
+		assert!(unescape("\\344").is_err());
+	}
+
+	#[test]
+	fn unescape_in_other_charsets() {
+		let latin1 = |bytes: &[u8]| Ok(bytes.iter().map(|b| *b as char).collect());
+		assert_eq!(unescape_with("gr\\366\\337e \\x41", &latin1).unwrap(), "größe A");
+		let file = parse_with_decoder("msgid \"a\"\nmsgstr \"\\344\"\n", &latin1).unwrap();
+		assert_eq!(file.entries[0].msgstr, vec!["ä"]);
+		assert!(parse("msgid \"a\"\nmsgstr \"\\344\"\n").is_err());
+	}
+
+	#[test]
+	fn escape_roundtrip() {
+		let s = "line 1\nsays \"hi\"\t\\ \u{7}";
+		assert_eq!(escape(s), "line 1\\nsays \\\"hi\\\"\\t\\\\ \\007");
+		assert_eq!(unescape(&escape(s)).unwrap(), s);
+	}
+
+	#[test]
+	fn quoted_strings() {
+		assert_eq!(parse_quoted(" \"a\\\\\" ", &decode_utf8).unwrap(), "a\\");
+		assert!(parse_quoted("\"a\\\"", &decode_utf8).is_err());
+		assert!(parse_quoted("\"a", &decode_utf8).is_err());
+		assert!(parse_quoted("a", &decode_utf8).is_err());
+	}
+
+	#[test]
+	fn parse_entries() {
+		let po = parse(r#"# translator comment
+#. extracted
+#: src/main.c:42 src/other.c:1
+#, c-format, fuzzy
+msgctxt "menu"
+msgid ""
+"Open "
+"%s"
+msgstr "Öffne %s"
+
+msgid "One file"
+msgid_plural "%d files"
+msgstr[0] "Eine Datei"
+msgstr[1] "%d Dateien"
+"#).unwrap();
+		assert_eq!(po.entries.len(), 2);
+		let e = &po.entries[0];
+		assert_eq!(e.translator_comments, vec!["translator comment"]);
+		assert_eq!(e.extracted_comments, vec!["extracted"]);
+		assert_eq!(e.references, vec!["src/main.c:42", "src/other.c:1"]);
+		assert!(e.has_flag("c-format") && e.has_flag("fuzzy"));
+		assert_eq!(e.msgctxt, Some("menu".to_string()));
+		assert_eq!(e.msgid, "Open %s");
+		assert_eq!(e.msgstr, vec!["Öffne %s"]);
+		assert!(!e.is_header());
+		let e = &po.entries[1];
+		assert_eq!(e.msgid_plural, Some("%d files".to_string()));
+		assert_eq!(e.msgstr, vec!["Eine Datei", "%d Dateien"]);
+		assert!(po.find(None, "One file").is_some());
+		assert!(po.find(None, "Open %s").is_none());
+		assert!(po.find(Some("menu"), "Open %s").is_some());
+	}
+
+	#[test]
+	fn parse_entries_without_blank_lines() {
+		let po = parse("msgid \"a\"\nmsgstr \"A\"\nmsgctxt \"c\"\nmsgid \"b\"\nmsgstr \"B\"\n#, fuzzy\nmsgid \"c\"\nmsgstr \"C\"\n").unwrap();
+		assert_eq!(po.entries.len(), 3);
+		assert_eq!(po.entries[1].msgctxt, Some("c".to_string()));
+		assert_eq!(po.entries[0].msgctxt, None);
+		assert!(po.entries[2].has_flag("fuzzy"));
+	}
+
+	#[test]
+	fn parse_previous_and_obsolete() {
+		let po = parse(r#"#| msgid "Old "
+#| "text"
+msgid "New text"
+msgstr "Neu"
+
+#~| msgid "Gone before"
+#~ msgid "Gone"
+#~ msgstr "Weg"
+"#).unwrap();
+		assert_eq!(po.entries[0].previous_msgid, Some("Old text".to_string()));
+		assert!(!po.entries[0].obsolete);
+		let e = &po.entries[1];
+		assert!(e.obsolete);
+		assert_eq!(e.previous_msgid, Some("Gone before".to_string()));
+		assert!(po.find(None, "Gone").is_none());
+		assert!(po.find_obsolete(None, "Gone").is_some());
+	}
+
+	#[test]
+	fn parse_header() {
+		let po = parse("msgid \"\"\nmsgstr \"\"\n\"Content-Type: text/plain; charset=ISO-8859-1\\n\"\n\"Plural-Forms: nplurals=2; plural=(n != 1);\\n\"\n").unwrap();
+		assert!(po.entries[0].is_header());
+		assert_eq!(po.charset(), Some("ISO-8859-1".to_string()));
+		assert_eq!(po.plural_forms().unwrap().unwrap().nplurals, 2);
+	}
+
+	#[test]
+	fn parse_errors() {
+		assert_eq!(parse("msgid \"a\"\n").unwrap_err().line, 2);
+		assert_eq!(parse("msgid \"a\"\nmsgstr[1] \"b\"\n").unwrap_err().line, 2);
+		assert_eq!(parse("msgstr \"a\"\n").unwrap_err().line, 1);
+		assert_eq!(parse("msgid \"a\"\nmsgstr \"b\"\nmsgstr \"c\"\n").unwrap_err().line, 3);
+		assert_eq!(parse("msgid \"a\"\nmsgstr \"b\\\"\n").unwrap_err().line, 2);
+		assert_eq!(parse("\"a\"\n").unwrap_err().line, 1);
+		assert_eq!(parse("msgid \"a\"\nmsgid_plural \"b\"\nmsgstr[0] \"c\"\nmsgid_plural \"d\"\n").unwrap_err().line, 4);
+	}
+}
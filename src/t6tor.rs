@@ -30,7 +30,11 @@ extern crate toml;
 use hyper::header::Authorization;
 use hyper::{Client, Url};
 use rustc_serialize::json;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 use url::form_urlencoded;
 
 pub trait Translator {
@@ -42,8 +46,8 @@ pub trait Translator {
 	fn attribution_info(&self) -> Option<String>;
 }
 
-#[allow(dead_code)]
-struct NoTranslator;
+/// Returns the text unchanged, for when we don't need a translation
+pub struct NoTranslator;
 
 impl Translator for NoTranslator {
 	#[allow(unused_variables)]
@@ -227,3 +231,148 @@ fn yn_translate(text: &str, translate_to: &str, lang_from: Option<&str>, api_key
 
 	return body_json.text[0].clone();
 }
+
+/********************************************************************
+* Cache
+*********************************************************************/
+
+#[derive(PartialEq, Eq, Hash)]
+struct CacheKey {
+	provider: String,
+	lang_from: String,
+	lang_to: String,
+	text: String,
+}
+
+struct CacheEntry {
+	translation: String,
+	/// When the translation was obtained, in seconds since the epoch
+	time: u64,
+}
+
+/// Wraps any translator, storing its translations on disk so that
+/// translating the same text again doesn't use up API quota
+pub struct CachedTranslator {
+	inner: Box<Translator>,
+	provider: String,
+	lang_to: String,
+	path: String,
+	max_entries: usize,
+	entries: RefCell<HashMap<CacheKey, CacheEntry>>,
+	changed: Cell<bool>,
+}
+
+fn unix_time() -> u64 {
+	return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+}
+
+impl CachedTranslator {
+	/// Loads the cache at path, leaving out entries older than max_age seconds
+	pub fn new(inner: Box<Translator>, provider: &str, lang_to: &str, path: &str,
+			max_entries: usize, max_age: u64) -> CachedTranslator {
+		let mut entries = HashMap::new();
+		let now = unix_time();
+		if let Ok(mut f) = File::open(path) {
+			let mut s = String::new();
+			f.read_to_string(&mut s)
+				.ok()
+				.expect(&format!("Failed to read translation cache '{}'", path));
+			let mut parser = toml::Parser::new(&s);
+			let tbl = match parser.parse() {
+				Some(tbl) => tbl,
+				None => panic!("parse error in translation cache '{}': {:?}", path, parser.errors),
+			};
+			for entry in tbl.get("entry").and_then(|e| e.as_slice()).unwrap_or(&[]) {
+				let field = |name| entry.lookup(name).and_then(|v| v.as_str()).unwrap_or("").to_string();
+				let time = entry.lookup("time").and_then(|v| v.as_integer()).unwrap_or(0) as u64;
+				if time + max_age < now {
+					continue;
+				}
+				entries.insert(CacheKey {
+					provider: field("provider"),
+					lang_from: field("from"),
+					lang_to: field("to"),
+					text: field("text"),
+				}, CacheEntry { translation: field("translation"), time: time });
+			}
+		}
+		return CachedTranslator {
+			inner: inner,
+			provider: provider.to_string(),
+			lang_to: lang_to.to_string(),
+			path: path.to_string(),
+			max_entries: max_entries,
+			entries: RefCell::new(entries),
+			changed: Cell::new(false),
+		};
+	}
+
+	/// Writes the cache to disk if it changed, keeping only the max_entries most recent entries
+	pub fn save(&self) -> io::Result<()> {
+		if !self.changed.get() {
+			return Ok(());
+		}
+		let entries = self.entries.borrow();
+		let mut list = entries.iter().collect::<Vec<_>>();
+		list.sort_by(|a, b| b.1.time.cmp(&a.1.time));
+		list.truncate(self.max_entries);
+		let mut arr = Vec::new();
+		for (key, entry) in list {
+			let mut tbl = toml::Table::new();
+			tbl.insert("provider".to_string(), toml::Value::String(key.provider.clone()));
+			tbl.insert("from".to_string(), toml::Value::String(key.lang_from.clone()));
+			tbl.insert("to".to_string(), toml::Value::String(key.lang_to.clone()));
+			tbl.insert("text".to_string(), toml::Value::String(key.text.clone()));
+			tbl.insert("translation".to_string(), toml::Value::String(entry.translation.clone()));
+			tbl.insert("time".to_string(), toml::Value::Integer(entry.time as i64));
+			arr.push(toml::Value::Table(tbl));
+		}
+		let mut root = toml::Table::new();
+		root.insert("entry".to_string(), toml::Value::Array(arr));
+		let mut f = try!(File::create(&self.path));
+		try!(f.write_all(toml::Value::Table(root).to_string().as_bytes()));
+		self.changed.set(false);
+		return Ok(());
+	}
+}
+
+impl Translator for CachedTranslator {
+	fn translate(&self, text: &str, lang_from: Option<&str>) -> String {
+		let key = CacheKey {
+			provider: self.provider.clone(),
+			lang_from: lang_from.unwrap_or("").to_string(),
+			lang_to: self.lang_to.clone(),
+			text: text.to_string(),
+		};
+		if let Some(entry) = self.entries.borrow().get(&key) {
+			return entry.translation.clone();
+		}
+		let translation = self.inner.translate(text, lang_from);
+		self.entries.borrow_mut().insert(key, CacheEntry { translation: translation.clone(), time: unix_time() });
+		self.changed.set(true);
+		return translation;
+	}
+	fn translate_s(&self, text: &str) -> String {
+		return self.translate(text, None);
+	}
+	fn attribution_info(&self) -> Option<String> {
+		return self.inner.attribution_info();
+	}
+}
+
+/// Fallback for when save wasn't called after the last translation
+impl Drop for CachedTranslator {
+	fn drop(&mut self) {
+		if let Err(e) = self.save() {
+			println!("Failed to write translation cache '{}': {}", self.path, e);
+		}
+	}
+}
+
+/// Removes the cache file at path, if there is one
+pub fn clear_translation_cache(path: &str) -> io::Result<()> {
+	return match fs::remove_file(path) {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+		res => res,
+	};
+}
@@ -0,0 +1,135 @@
+// podiff-review
+// review po changes easily
+//
+// The MIT License (MIT)
+//
+// Copyright 2015 est31 <MTest31@outlook.com>
+/*
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+*/
+
+/// Splits a string into words and the whitespace between them
+fn tokenize(s: &str) -> Vec<&str> {
+	let mut res = Vec::new();
+	let mut start = 0;
+	let mut last_ws = None;
+	for (i, c) in s.char_indices() {
+		let ws = c.is_whitespace();
+		if last_ws.is_some() && last_ws != Some(ws) {
+			res.push(&s[start .. i]);
+			start = i;
+		}
+		last_ws = Some(ws);
+	}
+	if start < s.len() {
+		res.push(&s[start ..]);
+	}
+	return res;
+}
+
+/// Returns a word diff of the two strings, in the style of git diff --word-diff
+/// ("[-removed-]{+added+}")
+pub fn word_diff(old: &str, new: &str) -> String {
+	let a = tokenize(old);
+	let b = tokenize(new);
+	// lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+	let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+	for i in (0 .. a.len()).rev() {
+		for j in (0 .. b.len()).rev() {
+			lcs[i][j] = if a[i] == b[j] {
+				lcs[i + 1][j + 1] + 1
+			} else {
+				::std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+			};
+		}
+	}
+	let mut res = String::new();
+	let mut removed = String::new();
+	let mut added = String::new();
+	macro_rules! flush {
+		() => {
+			if !removed.is_empty() {
+				res.push_str(&format!("[-{}-]", removed));
+				removed.clear();
+			}
+			if !added.is_empty() {
+				res.push_str(&format!("{{+{}+}}", added));
+				added.clear();
+			}
+		}
+	}
+	let (mut i, mut j) = (0, 0);
+	while i < a.len() || j < b.len() {
+		if i < a.len() && j < b.len() && a[i] == b[j] {
+			flush!();
+			res.push_str(a[i]);
+			i += 1;
+			j += 1;
+		} else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+			removed.push_str(a[i]);
+			i += 1;
+		} else {
+			added.push_str(b[j]);
+			j += 1;
+		}
+	}
+	flush!();
+	return res;
+}
+
+fn normalized_words(s: &str) -> Vec<String> {
+	return s.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(|w| w.to_lowercase())
+		.collect();
+}
+
+/// Whether the change is more than whitespace, punctuation or case
+pub fn is_meaningful_change(old: &str, new: &str) -> bool {
+	return normalized_words(old) != normalized_words(new);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn words() {
+		assert_eq!(word_diff("the same text", "the same text"), "the same text");
+		assert_eq!(word_diff("open the file", "open the new file"), "open the {+new +}file");
+		assert_eq!(word_diff("open the old file", "open the file"), "open the [-old -]file");
+		assert_eq!(word_diff("open the file", "close the file"), "[-open-]{+close+} the file");
+		assert_eq!(word_diff("", "new"), "{+new+}");
+		assert_eq!(word_diff("old", ""), "[-old-]");
+	}
+
+	#[test]
+	fn whitespace_and_unicode() {
+		assert_eq!(word_diff("a  b", "a b"), "a[-  -]{+ +}b");
+		assert_eq!(word_diff("größe ändern", "größe  ändern"), "größe[- -]{+  +}ändern");
+	}
+
+	#[test]
+	fn meaningful() {
+		assert!(!is_meaningful_change("Open file...", "open  file"));
+		assert!(!is_meaningful_change("Save", "Save:"));
+		assert!(is_meaningful_change("Save file", "Save files"));
+		assert!(is_meaningful_change("Save", "Save as"));
+	}
+}